@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+use log::*;
+use osquery_rs::OSQuery;
+use regex::Regex;
+use serde_json::{json, Value};
+
+/// The type of an osquery table column, osquery declares the columns as `TEXT`, `INTEGER`, `BIGINT`, `UNSIGNED BIGINT`, `DOUBLE` or `BLOB`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    UnsignedInteger,
+    Double,
+    Text,
+}
+
+impl ColumnType {
+    fn from_declared(declared: &str) -> Self {
+        let declared = declared.to_uppercase();
+        if declared.contains("UNSIGNED") {
+            Self::UnsignedInteger
+        } else if declared.contains("INT") {
+            Self::Integer
+        } else if declared.contains("DOUBLE") || declared.contains("REAL") {
+            Self::Double
+        } else {
+            Self::Text
+        }
+    }
+
+    /// Convert the value returned by osquery to the column type, osquery returns an empty string for `NULL` numbers.
+    /// Values that can not be converted are kept as strings
+    fn convert(&self, value: &str) -> Value {
+        if *self != Self::Text && value.is_empty() {
+            return Value::Null;
+        }
+        let converted = match self {
+            Self::Integer => value.parse::<i64>().ok().map(|n| json!(n)),
+            Self::UnsignedInteger => value.parse::<u64>().ok().map(|n| json!(n)),
+            Self::Double => value
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number),
+            Self::Text => None,
+        };
+        converted.unwrap_or_else(|| Value::String(value.to_string()))
+    }
+}
+
+/// Cache of the column types of the osquery tables used to output the query results with their types instead of strings.
+/// The types are read from the tables in the `FROM` & `JOIN` clauses of the query, the columns that are not found in these
+/// tables (ex. expressions and aliases) are kept as strings
+#[derive(Debug, Default)]
+pub struct ColumnTypes {
+    tables: Mutex<HashMap<String, HashMap<String, ColumnType>>>,
+}
+
+impl ColumnTypes {
+    /// Lock the cache, the lock is recovered if another worker panicked while holding it
+    fn tables(&self) -> MutexGuard<'_, HashMap<String, HashMap<String, ColumnType>>> {
+        match self.tables.lock() {
+            Ok(tables) => tables,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Returns the column types of the table, the types are read once for every table
+    fn table(&self, osquery_instance: &OSQuery, table: &str) -> HashMap<String, ColumnType> {
+        if let Some(columns) = self.tables().get(table) {
+            return columns.clone();
+        }
+
+        let sql = format!("select name, type from pragma_table_info('{}')", table);
+        let columns: HashMap<String, ColumnType> = match osquery_instance.query(sql) {
+            Ok(res) if res.status.as_ref().and_then(|status| status.code) == Some(0) => res
+                .response
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|row| {
+                    Some((
+                        row.get("name")?.to_string(),
+                        ColumnType::from_declared(row.get("type")?),
+                    ))
+                })
+                .collect(),
+            _ => {
+                debug!(
+                    "Unable to read the column types of the table '{}', its columns are kept as strings",
+                    table
+                );
+                HashMap::new()
+            }
+        };
+        self.tables().insert(table.to_string(), columns.clone());
+        columns
+    }
+
+    /// Returns the column types of the tables used by the query, when a column is in multiple tables the first table is used
+    pub fn for_query(&self, osquery_instance: &OSQuery, sql: &str) -> HashMap<String, ColumnType> {
+        let mut types = HashMap::new();
+        for table in query_tables(sql) {
+            for (column, column_type) in self.table(osquery_instance, &table) {
+                types.entry(column).or_insert(column_type);
+            }
+        }
+        types
+    }
+}
+
+/// Returns the names of the tables in the `FROM` & `JOIN` clauses of the query
+pub fn query_tables(sql: &str) -> Vec<String> {
+    let tables_re = Regex::new(r"(?i)\b(?:from|join)\s+([a-z_][a-z0-9_]*)").unwrap();
+    tables_re
+        .captures_iter(sql)
+        .map(|table| table[1].to_lowercase())
+        .collect()
+}
+
+/// Convert an osquery result row to a JSON object using the column types, the columns without types are kept as strings
+pub fn convert_row(
+    types: &HashMap<String, ColumnType>,
+    row: &BTreeMap<String, String>,
+) -> serde_json::Map<String, Value> {
+    row.iter()
+        .map(|(column, value)| {
+            let value = match types.get(column) {
+                Some(column_type) => column_type.convert(value),
+                None => Value::String(value.to_string()),
+            };
+            (column.to_string(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_declared_reads_osquery_types() {
+        assert_eq!(ColumnType::from_declared("BIGINT"), ColumnType::Integer);
+        assert_eq!(
+            ColumnType::from_declared("UNSIGNED BIGINT"),
+            ColumnType::UnsignedInteger
+        );
+        assert_eq!(ColumnType::from_declared("double"), ColumnType::Double);
+        assert_eq!(ColumnType::from_declared("BLOB"), ColumnType::Text);
+    }
+
+    #[test]
+    fn convert_keeps_invalid_values_as_strings() {
+        assert_eq!(ColumnType::Integer.convert("-12"), json!(-12));
+        assert_eq!(
+            ColumnType::UnsignedInteger.convert("18446744073709551615"),
+            json!(u64::MAX)
+        );
+        assert_eq!(ColumnType::Double.convert("0.5"), json!(0.5));
+        assert_eq!(ColumnType::Integer.convert(""), Value::Null);
+        assert_eq!(ColumnType::Text.convert(""), json!(""));
+        assert_eq!(ColumnType::Integer.convert("12a"), json!("12a"));
+        assert_eq!(ColumnType::UnsignedInteger.convert("-1"), json!("-1"));
+        assert_eq!(ColumnType::Double.convert("NaN"), json!("NaN"));
+    }
+
+    #[test]
+    fn query_tables_reads_from_and_join() {
+        assert_eq!(
+            query_tables(
+                "SELECT p.pid, u.username FROM Processes p\n  LEFT JOIN users u USING (uid) where name like '%from%'"
+            ),
+            vec!["processes", "users"]
+        );
+        assert!(query_tables("select 1").is_empty());
+    }
+
+    #[test]
+    fn convert_row_uses_the_column_types() {
+        let types = HashMap::from([(String::from("pid"), ColumnType::Integer)]);
+        let row = BTreeMap::from([
+            (String::from("pid"), String::from("42")),
+            (String::from("name"), String::from("7")),
+        ]);
+        let converted = convert_row(&types, &row);
+        assert_eq!(converted["pid"], json!(42));
+        assert_eq!(converted["name"], json!("7"));
+    }
+}
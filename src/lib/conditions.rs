@@ -0,0 +1,155 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    os::unix::prelude::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use log::*;
+use osquery_rs::OSQuery;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Preconditions for running an artifact, all the specified conditions should be true for the artifact to run:
+/// * path_exists: glob pattern that should match at least one path
+/// * binary_exists: executable name that should be found in `$PATH`
+/// * query: osquery SQL query that should return at least one row
+/// * os_family: list of OS families, the OS should match any of them (ex. `linux`, `debian`, `rhel`)
+pub struct Condition {
+    /// Glob pattern that should match at least one path (ex. `/etc/apt`)
+    path_exists: Option<String>,
+    /// Executable name that should be found in `$PATH` (ex. `dpkg`)
+    binary_exists: Option<String>,
+    /// osquery SQL query that should return at least one row
+    query: Option<String>,
+    /// OS families, the OS should match any of them. The OS family is the target OS (`linux`, `macos` or `freebsd`) and the fields `ID` & `ID_LIKE` in `/etc/os-release` (ex. `debian`, `rhel`)
+    os_family: Option<Vec<String>>,
+}
+
+impl Condition {
+    /// Evaluate the conditions, the reason is returned if any of the conditions is false
+    pub fn evaluate(&self, osquery_instance: Option<&OSQuery>) -> Result<(), String> {
+        self.evaluate_local()?;
+
+        if let Some(sql) = &self.query {
+            let instance = match osquery_instance {
+                Some(instance) => instance,
+                None => {
+                    return Err(format!(
+                        "the osquery SQL query '{}' can not be executed, osquery instance is not available",
+                        sql
+                    ))
+                }
+            };
+            match instance.query(sql.to_string()) {
+                Ok(res) => {
+                    let code = res.status.and_then(|status| status.code).unwrap_or(1);
+                    let rows = res.response.map(|data| data.len()).unwrap_or(0);
+                    if code != 0 || rows == 0 {
+                        return Err(format!("the osquery SQL query '{}' returned no rows", sql));
+                    }
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "unable to execute the osquery SQL query '{}', ERROR: {}",
+                        sql, e
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the conditions that do not require osquery
+    pub fn evaluate_local(&self) -> Result<(), String> {
+        if let Some(pattern) = &self.path_exists {
+            let exists = match glob::glob(pattern) {
+                Ok(mut paths) => paths.any(|path| path.is_ok()),
+                Err(e) => {
+                    return Err(format!(
+                        "the glob pattern '{}' is invalid, ERROR: {}",
+                        pattern, e
+                    ))
+                }
+            };
+            if !exists {
+                return Err(format!("the path '{}' does not exist", pattern));
+            }
+        }
+
+        if let Some(binary) = &self.binary_exists {
+            if find_binary(binary).is_none() {
+                return Err(format!("the binary '{}' was not found in $PATH", binary));
+            }
+        }
+
+        if let Some(families) = &self.os_family {
+            let os_families = os_families();
+            if !families
+                .iter()
+                .any(|family| os_families.contains(&family.to_lowercase()))
+            {
+                return Err(format!(
+                    "the OS family {:?} does not match any of {:?}",
+                    os_families, families
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the osquery SQL query condition if specified
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+}
+
+/// Search for an executable in the directories in `$PATH`, names containing `/` are checked as paths
+pub fn find_binary(name: &str) -> Option<PathBuf> {
+    let is_executable = |path: &Path| match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    };
+
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return match is_executable(&path) {
+            true => Some(path),
+            false => None,
+        };
+    }
+
+    let path_env = env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into());
+    env::split_paths(&path_env)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+/// Returns the names of the running OS family in lowercase: the target OS (ex. `linux`) and the fields `ID` & `ID_LIKE` from `/etc/os-release`
+pub fn os_families() -> Vec<String> {
+    let mut families = vec![env::consts::OS.to_string()];
+    let os_release = match File::open("/etc/os-release") {
+        Ok(f) => f,
+        Err(e) => {
+            debug!("Unable to read '/etc/os-release', ERROR: {}", e);
+            return families;
+        }
+    };
+    for line in BufReader::new(os_release).lines().map_while(Result::ok) {
+        if let Some((key, value)) = line.split_once('=') {
+            if key == "ID" || key == "ID_LIKE" {
+                value
+                    .trim_matches(|c| c == '"' || c == '\'')
+                    .split_whitespace()
+                    .for_each(|family| families.push(family.to_lowercase()));
+            }
+        }
+    }
+    families
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::*;
+
+use crate::{
+    errors::FennecError,
+    manifest::{self, ConfigFile},
+    validate::{self, Issue},
+    Artifact, FennecConfig, OSQueryOptions,
+};
+
+/// Resolves the `include` directives of configurations, every artifact is recorded with the file that defined it
+/// so duplicated names can be reported with both files
+struct Loader {
+    artifacts: Vec<Artifact>,
+    /// The `osquery` sections of the configurations merged in the loading order
+    osquery: OSQueryOptions,
+    defined_in: HashMap<String, String>,
+    /// The files being loaded, used to detect include cycles
+    stack: Vec<PathBuf>,
+    /// The files already loaded, a file included by more than one configuration is loaded once
+    loaded: Vec<PathBuf>,
+    /// The hash of every loaded file, recorded in the collection information
+    files: Vec<ConfigFile>,
+    /// Set in validation mode, all the problems are collected instead of stopping at the first one
+    issues: Option<Vec<Issue>>,
+}
+
+/// Parse the configuration and the configurations it includes. The `include` paths are relative to `base_dir` and support glob,
+/// a directory includes all the `.yaml` & `.yml` files in it. `source` is the name of the configuration used in the error messages.
+/// Returns the merged configuration with the hashes of the loaded files
+pub fn load(
+    raw: &[u8],
+    source: &str,
+    base_dir: &Path,
+) -> Result<(FennecConfig, Vec<ConfigFile>), FennecError> {
+    let mut loader = Loader::new(None);
+    loader.load(raw, source, base_dir)?;
+    let config = FennecConfig {
+        args: None,
+        profiles: None,
+        include: None,
+        osquery: match loader.osquery.flags.is_empty() && loader.osquery.extensions.is_empty() {
+            true => None,
+            false => Some(loader.osquery),
+        },
+        artifacts: loader.artifacts,
+    };
+    Ok((config, loader.files))
+}
+
+/// Same as `load` but all the problems in the configurations are returned, including the problems that do not stop Fennec from running
+pub fn validate(raw: &[u8], source: &str, base_dir: &Path) -> Vec<Issue> {
+    let mut loader = Loader::new(Some(vec![]));
+    // Nothing is returned in validation mode, the problems are collected
+    let _ = loader.load(raw, source, base_dir);
+    loader.issues.unwrap_or_default()
+}
+
+impl Loader {
+    fn new(issues: Option<Vec<Issue>>) -> Self {
+        Self {
+            artifacts: vec![],
+            osquery: OSQueryOptions::default(),
+            defined_in: HashMap::new(),
+            stack: vec![],
+            loaded: vec![],
+            files: vec![],
+            issues,
+        }
+    }
+
+    /// Record the problem in validation mode, otherwise return it as an error
+    fn report(&mut self, issue: Issue) -> Result<(), FennecError> {
+        match &mut self.issues {
+            Some(issues) => {
+                issues.push(issue);
+                Ok(())
+            }
+            None => Err(FennecError::config_error(issue.message)),
+        }
+    }
+
+    fn issue(source: &str, artifact: Option<&str>, line: Option<usize>, message: String) -> Issue {
+        Issue {
+            file: source.to_string(),
+            line,
+            column: None,
+            artifact: artifact.map(|a| a.to_string()),
+            message,
+        }
+    }
+
+    fn parse(&mut self, raw: &[u8], source: &str) -> Result<Option<FennecConfig>, FennecError> {
+        let mut unknown = vec![];
+        let res = match self.issues {
+            Some(_) => {
+                serde_ignored::deserialize(serde_yaml::Deserializer::from_slice(raw), |path| {
+                    // Optional fields add '?' to the path
+                    unknown.push(path.to_string().replace(".?", ""))
+                })
+            }
+            None => serde_yaml::from_slice(raw),
+        };
+        let config: FennecConfig = match res {
+            Ok(config) => config,
+            Err(e) => {
+                let location = e.location();
+                self.report(Issue {
+                    file: source.to_string(),
+                    line: location.as_ref().map(|l| l.line()),
+                    column: location.as_ref().map(|l| l.column()),
+                    artifact: None,
+                    message: format!(
+                        "Syntax error in the configuration '{}', ERROR: {}",
+                        source, e
+                    ),
+                })?;
+                return Ok(None);
+            }
+        };
+
+        for path in unknown {
+            // The path looks like 'artifacts.3.regx', point to the artifact name if possible
+            let index = path
+                .strip_prefix("artifacts.")
+                .and_then(|rest| rest.split('.').next())
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index < config.artifacts.len());
+            let artifact = index.map(|index| config.artifacts[index].name.clone());
+            let field = path.rsplit('.').next().unwrap_or(&path).to_string();
+            let line = index.and_then(|index| {
+                let name = &config.artifacts[index].name;
+                let occurrence = config.artifacts[..index]
+                    .iter()
+                    .filter(|other| &other.name == name)
+                    .count();
+                validate::artifact_line(raw, name, occurrence)
+            });
+            self.report(Self::issue(
+                source,
+                artifact.as_deref(),
+                line,
+                format!("Unknown field '{}' at '{}'", field, path),
+            ))?;
+        }
+        Ok(Some(config))
+    }
+
+    fn load(&mut self, raw: &[u8], source: &str, base_dir: &Path) -> Result<(), FennecError> {
+        self.files.push(ConfigFile {
+            path: source.to_string(),
+            sha256: manifest::sha256(raw),
+        });
+        let config = match self.parse(raw, source)? {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+
+        // Only a warning, reported in validation mode
+        if self.issues.is_some()
+            && !self.stack.is_empty()
+            && (config.args.is_some() || config.profiles.is_some())
+        {
+            self.report(Self::issue(
+                source,
+                None,
+                None,
+                String::from(
+                    "The fields 'args' & 'profiles' of included configurations are ignored",
+                ),
+            ))?;
+        }
+
+        if let Some(osquery) = config.osquery {
+            self.osquery.flags.extend(osquery.flags);
+            self.osquery.extensions.extend(
+                osquery
+                    .extensions
+                    .iter()
+                    .map(|extension| base_dir.join(extension).to_string_lossy().to_string()),
+            );
+        }
+
+        for pattern in config.include.unwrap_or_default() {
+            match Self::resolve(&pattern, source, base_dir) {
+                Ok(paths) => {
+                    for path in paths {
+                        self.load_file(&path, source)?;
+                    }
+                }
+                Err(message) => self.report(Self::issue(source, None, None, message))?,
+            }
+        }
+
+        // The number of definitions of each name in this configuration, a duplicate points to its own definition
+        let mut occurrences: HashMap<String, usize> = HashMap::new();
+        for artifact in config.artifacts {
+            let occurrence = occurrences.entry(artifact.name.clone()).or_default();
+            let line = validate::artifact_line(raw, &artifact.name, *occurrence);
+            *occurrence += 1;
+            if self.issues.is_some() {
+                for problem in validate::check_artifact(&artifact) {
+                    self.report(Self::issue(
+                        source,
+                        Some(&artifact.name),
+                        line,
+                        format!("The artifact '{}': {}", artifact.name, problem),
+                    ))?;
+                }
+            }
+            if let Some(other) = self.defined_in.get(&artifact.name) {
+                let message = format!(
+                    "The artifact '{}' is defined in '{}' and '{}', artifact names should be unique",
+                    artifact.name, other, source
+                );
+                self.report(Self::issue(source, Some(&artifact.name), line, message))?;
+                continue;
+            }
+            self.defined_in
+                .insert(artifact.name.clone(), source.to_string());
+            self.artifacts.push(artifact);
+        }
+        Ok(())
+    }
+
+    fn load_file(&mut self, path: &Path, included_by: &str) -> Result<(), FennecError> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.stack.contains(&canonical) {
+            return self.report(Self::issue(
+                included_by,
+                None,
+                None,
+                format!(
+                    "The configuration '{}' includes itself through '{}'",
+                    path.to_string_lossy(),
+                    included_by
+                ),
+            ));
+        }
+        if self.loaded.contains(&canonical) {
+            debug!(
+                "The configuration '{}' included by '{}' is already loaded, skipping it",
+                path.to_string_lossy(),
+                included_by
+            );
+            return Ok(());
+        }
+        let raw = match fs::read(path) {
+            Ok(raw) => raw,
+            Err(e) => {
+                return self.report(Self::issue(
+                    included_by,
+                    None,
+                    None,
+                    format!(
+                        "Can not read the configuration '{}' included by '{}', ERROR: {}",
+                        path.to_string_lossy(),
+                        included_by,
+                        e
+                    ),
+                ))
+            }
+        };
+        debug!(
+            "Loading the configuration '{}' included by '{}'",
+            path.to_string_lossy(),
+            included_by
+        );
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        self.stack.push(canonical.clone());
+        self.loaded.push(canonical);
+        let res = self.load(&raw, &path.to_string_lossy(), base_dir);
+        self.stack.pop();
+        res
+    }
+
+    /// Expand an include pattern to the configuration files it matches
+    fn resolve(pattern: &str, source: &str, base_dir: &Path) -> Result<Vec<PathBuf>, String> {
+        let full_pattern = base_dir.join(pattern);
+        let paths = glob::glob(&full_pattern.to_string_lossy()).map_err(|e| {
+            format!(
+                "Invalid include pattern '{}' in the configuration '{}', ERROR: {}",
+                pattern, source, e
+            )
+        })?;
+
+        let mut files = vec![];
+        for path in paths.flatten() {
+            if path.is_dir() {
+                let mut dir_files: Vec<PathBuf> = fs::read_dir(&path)
+                    .map_err(|e| {
+                        format!(
+                            "Can not read the directory '{}' included by '{}', ERROR: {}",
+                            path.to_string_lossy(),
+                            source,
+                            e
+                        )
+                    })?
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.is_file()
+                            && matches!(
+                                path.extension().and_then(|e| e.to_str()),
+                                Some("yaml") | Some("yml")
+                            )
+                    })
+                    .collect();
+                // Load the files in the same order on every system
+                dir_files.sort();
+                files.append(&mut dir_files);
+            } else {
+                files.push(path);
+            }
+        }
+
+        if files.is_empty() {
+            return Err(format!(
+                "The include '{}' in the configuration '{}' does not match any file",
+                pattern, source
+            ));
+        }
+        Ok(files)
+    }
+}
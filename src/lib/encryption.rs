@@ -0,0 +1,232 @@
+use std::{
+    io::{Error, Write},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use age::{stream::StreamWriter, x25519::Recipient, Encryptor};
+use log::*;
+
+use crate::{errors::FennecError, sink::OutputSink};
+
+/// Parse age X25519 public keys (ex. `age1...`), these keys can be generated using `age-keygen`
+pub fn parse_recipients(keys: &[String]) -> Result<Vec<Recipient>, FennecError> {
+    keys.iter()
+        .map(|key| {
+            key.trim().parse::<Recipient>().map_err(|e| {
+                FennecError::encryption_error(format!(
+                    "Invalid recipient public key '{}', ERROR: {}",
+                    key, e
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Encrypts the data written to it using age for the recipients, only the holders of the recipients private keys can decrypt it.
+/// The clones of the writer write to the same encrypted stream, the stream should be finished using `finish` after the last write
+/// because the data can not be decrypted without its last chunk. The last clone dropped finishes the stream if it is not finished
+pub struct EncryptedWriter<W: Write> {
+    inner: Arc<Mutex<Option<StreamWriter<W>>>>,
+}
+
+impl<W: Write> Clone for EncryptedWriter<W> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    pub fn new(writer: W, recipients: &[Recipient]) -> Result<Self, Error> {
+        let recipients = recipients
+            .iter()
+            .map(|recipient| Box::new(recipient.clone()) as Box<dyn age::Recipient>)
+            .collect();
+        let inner = Encryptor::with_recipients(recipients)
+            .wrap_output(writer)
+            .map_err(|e| Error::other(format!("Unable to start the encryption, ERROR: {}", e)))?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Some(inner))),
+        })
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Option<StreamWriter<W>>> {
+        match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Write the last encrypted chunk and return the inner writer
+    pub fn finish(&self) -> Result<W, Error> {
+        match self.inner().take() {
+            Some(inner) => inner.finish(),
+            None => Err(Error::other("the encrypted stream is already finished")),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self.inner().as_mut() {
+            Some(inner) => inner.write(buf),
+            None => Err(Error::other("the encrypted stream is already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self.inner().as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.inner) > 1 {
+            return;
+        }
+        if let Some(inner) = self.inner().take() {
+            warn!("The encrypted stream was not finished, finishing it");
+            if let Err(e) = inner.finish().and_then(|mut w| w.flush()) {
+                error!("Unable to finish the encrypted stream, ERROR: '{}'", e);
+            }
+        }
+    }
+}
+
+/// Wraps the output sink writing to an `EncryptedWriter`, the encrypted stream is finished when the package is finished
+pub struct EncryptedSink<W: Write + Send> {
+    sink: Box<dyn OutputSink>,
+    writer: EncryptedWriter<W>,
+}
+
+impl<W: Write + Send> EncryptedSink<W> {
+    /// `sink` writes the package to a clone of `writer`
+    pub fn new(sink: Box<dyn OutputSink>, writer: EncryptedWriter<W>) -> Self {
+        Self { sink, writer }
+    }
+}
+
+impl<W: Write + Send> Write for EncryptedSink<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.sink.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.sink.flush()
+    }
+}
+
+impl<W: Write + Send> OutputSink for EncryptedSink<W> {
+    fn start_entry(&mut self, name: &str) -> Result<(), Error> {
+        self.sink.start_entry(name)
+    }
+
+    fn start_sized_entry(&mut self, name: &str, size: u64) -> Result<(), Error> {
+        self.sink.start_sized_entry(name, size)
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.sink.is_streaming()
+    }
+
+    fn end_entry(&mut self) -> Result<(), Error> {
+        self.sink.end_entry()
+    }
+
+    fn add_directory(&mut self, name: &str) -> Result<(), Error> {
+        self.sink.add_directory(name)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.sink.finish()?;
+        self.writer.finish()?.flush()?;
+        debug!("Finished writing the encrypted stream");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use age::x25519::Identity;
+
+    use super::*;
+    use crate::sink::TarSink;
+
+    /// Writes to a shared buffer and fails once the buffer reaches `capacity` bytes like a full disk
+    #[derive(Clone)]
+    struct LimitedWriter {
+        data: Arc<Mutex<Vec<u8>>>,
+        capacity: usize,
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            let mut data = self.data.lock().unwrap();
+            if data.len() + buf.len() > self.capacity {
+                return Err(Error::other("no space left on device"));
+            }
+            data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn encrypted_sink(
+        identity: &Identity,
+        capacity: usize,
+    ) -> (EncryptedSink<LimitedWriter>, LimitedWriter) {
+        let output = LimitedWriter {
+            data: Arc::new(Mutex::new(vec![])),
+            capacity,
+        };
+        let writer = EncryptedWriter::new(output.clone(), &[identity.to_public()]).unwrap();
+        let sink = EncryptedSink::new(Box::new(TarSink::new(writer.clone())), writer);
+        (sink, output)
+    }
+
+    #[test]
+    fn finish_writes_the_last_chunk() {
+        let identity = Identity::generate();
+        let (mut sink, output) = encrypted_sink(&identity, usize::MAX);
+        sink.start_entry("users.jsonl").unwrap();
+        sink.write_all(b"{\"username\":\"root\"}\n").unwrap();
+        sink.end_entry().unwrap();
+        sink.finish().unwrap();
+
+        let data = output.data.lock().unwrap().clone();
+        let decryptor = match age::Decryptor::new(data.as_slice()).unwrap() {
+            age::Decryptor::Recipients(decryptor) => decryptor,
+            _ => unreachable!(),
+        };
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap();
+        let mut package = vec![];
+        reader.read_to_end(&mut package).unwrap();
+        let mut archive = tar::Archive::new(package.as_slice());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "{\"username\":\"root\"}\n");
+    }
+
+    #[test]
+    fn finish_reports_the_last_chunk_errors() {
+        let identity = Identity::generate();
+        // Enough for the age header only, the encrypted chunks are written when the stream is finished
+        let (mut sink, _) = encrypted_sink(&identity, 512);
+        sink.start_entry("users.jsonl").unwrap();
+        sink.write_all(b"{\"username\":\"root\"}\n").unwrap();
+        sink.end_entry().unwrap();
+        assert!(sink.finish().is_err());
+    }
+}
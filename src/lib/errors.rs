@@ -5,6 +5,8 @@ pub enum ErrorType {
     Query,
     UploadConfigError,
     UploadError,
+    Output,
+    Encryption,
 }
 #[derive(Debug)]
 pub struct FennecError {
@@ -14,32 +16,44 @@ pub struct FennecError {
 impl FennecError {
     pub fn config_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::Config,
         }
     }
     pub fn osquery_instance_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::OSQueryInstance,
         }
     }
     pub fn query_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::Query,
         }
     }
     pub fn upload_config_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::UploadConfigError,
         }
     }
     pub fn upload_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::UploadError,
         }
     }
+    pub fn output_error(message: String) -> Self {
+        Self {
+            message,
+            kind: ErrorType::Output,
+        }
+    }
+    pub fn encryption_error(message: String) -> Self {
+        Self {
+            message,
+            kind: ErrorType::Encryption,
+        }
+    }
 }
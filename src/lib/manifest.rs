@@ -0,0 +1,124 @@
+use std::io::{Error, Write};
+
+use md5::Md5;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Wraps a writer and computes the SHA-256 and the size of the data written through it, the MD5 & SHA-1 are computed if enabled
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    md5: Option<Md5>,
+    sha1: Option<Sha1>,
+    size: u64,
+}
+
+/// The hex encoded hashes of the data written through `HashingWriter`, the MD5 & SHA-1 are only set if enabled
+#[derive(Debug, Clone)]
+pub struct Hashes {
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: String,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            md5: None,
+            sha1: None,
+            size: 0,
+        }
+    }
+
+    /// Also compute the MD5 and/or the SHA-1 in the same pass
+    pub fn with_hashes(mut self, md5: bool, sha1: bool) -> Self {
+        self.md5 = md5.then(Md5::new);
+        self.sha1 = sha1.then(Sha1::new);
+        self
+    }
+
+    /// Returns the size and the hex encoded SHA-256 of the written data
+    pub fn finalize(self) -> (u64, String) {
+        let (size, hashes) = self.finalize_hashes();
+        (size, hashes.sha256)
+    }
+
+    /// Returns the size and the hex encoded hashes of the written data
+    pub fn finalize_hashes(self) -> (u64, Hashes) {
+        let hashes = Hashes {
+            md5: self.md5.map(|md5| format!("{:x}", md5.finalize())),
+            sha1: self.sha1.map(|sha1| format!("{:x}", sha1.finalize())),
+            sha256: format!("{:x}", self.hasher.finalize()),
+        };
+        (self.size, hashes)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        if let Some(md5) = &mut self.md5 {
+            md5.update(&buf[..n]);
+        }
+        if let Some(sha1) = &mut self.sha1 {
+            sha1.update(&buf[..n]);
+        }
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the hex encoded SHA-256 of the data
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[derive(Debug, Serialize)]
+/// A single entry in the package `manifest.json`
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the stored content, directories do not have a hash
+    pub sha256: Option<String>,
+    /// The original path of collected files
+    pub source_path: Option<String>,
+    /// The artifact that produced the entry
+    pub artifact: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// A configuration file loaded by Fennec
+pub struct ConfigFile {
+    pub path: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+/// Chain of custody information written to the package as `collection_info.json`
+pub struct CollectionInfo {
+    pub hostname: String,
+    pub fennec_version: String,
+    /// SHA-256 of the configuration passed to Fennec
+    pub config_sha256: String,
+    /// The configuration and the configurations it includes, in the loading order
+    pub config_files: Vec<ConfigFile>,
+    /// The overrides applied to the configuration using `--set`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub config_overrides: Vec<String>,
+    pub operator_uid: u32,
+    pub effective_uid: u32,
+    /// The user that ran Fennec using sudo (`$SUDO_USER`)
+    pub sudo_user: Option<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub timezone: String,
+    pub manifest_sha256: String,
+}
@@ -1,9 +1,10 @@
-///! A library used to collect triage image from *nix machines
-use chrono::{DateTime, Utc};
+//! A library used to collect triage image from *nix machines
+use chrono::{DateTime, Local, Utc};
 use flate2::read::GzDecoder;
 use regex::{self, Regex};
 use std::time::Instant;
 use std::{
+    collections::{BTreeMap, HashMap},
     fmt::Display,
     fs::File,
     io::{Read, Write},
@@ -11,42 +12,113 @@ use std::{
     path::{Path, PathBuf},
     process::Stdio,
     result::Result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
     time::{Duration, SystemTime},
 };
-use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+mod column_types;
+mod config;
 mod errors;
+use column_types::ColumnTypes;
 use errors::FennecError;
-use glob;
+mod manifest;
 use log::*;
+use manifest::{CollectionInfo, ConfigFile, Hashes, HashingWriter, ManifestEntry};
+use native::NativeTables;
 use serde_json::{json, Value};
 use std::io::{prelude::*, BufReader};
 use std::process::Command;
+mod conditions;
 mod modifiers;
+mod native;
+mod overrides;
+use conditions::Condition;
 use csv::Writer;
 use modifiers::Modifier;
 mod traits;
+mod variables;
 use osquery_rs::OSQuery;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+mod staging;
+mod supervisor;
+use staging::StagedEntry;
+use supervisor::OSQuerySupervisor;
 use traits::ReadUntil;
+use variables::{Expanded, Variables};
 
+pub mod encryption;
+pub mod plan;
+pub mod report;
+pub mod schema;
+pub mod sink;
+pub mod throttle;
 pub mod upload;
+pub mod validate;
+use plan::{ArtifactPlan, PlanStatus, PlannedFile, TriagePlan};
+use report::{ArtifactReport, ArtifactStatus, TriageReport};
+use sink::OutputSink;
+use throttle::{RateLimiter, ThrottledReader, ThrottledWriter};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 /// Contains the configuration for the all artifacts
 pub struct FennecConfig {
+    /// Command line arguments used by the embedded configuration, the command line arguments override them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    /// Named sets of arguments and artifacts selected using `--profile`, used by the embedded configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<BTreeMap<String, Profile>>,
+    /// Configuration files, directories or glob patterns to load the artifacts from, relative to the configuration file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    include: Option<Vec<String>>,
+    /// Flags and extensions of the osquery instance spawned by Fennec, the sections of the included configurations are merged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    osquery: Option<OSQueryOptions>,
+    /// List of artifact definitions
+    #[serde(default)]
     artifacts: Vec<Artifact>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Named set of arguments and artifacts selected using `--profile`
+pub struct Profile {
+    /// Description of the profile, used only as a reference
+    pub description: Option<String>,
+    /// Command line arguments that override the arguments in the `args` section
+    pub args: Option<Vec<String>>,
+    /// Names of the artifacts to collect (supports glob), same as `--artifacts`
+    pub artifacts: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+/// Flags and extensions of the osquery instance spawned by Fennec, not used with `--osquery-socket`
+pub struct OSQueryOptions {
+    /// osquery flags added after the default flags so they override them (ex. `--disable_events`, `--disable_watchdog=false` with `--watchdog_memory_limit=1000`, the watchdog is disabled by default)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<String>,
+    /// Paths of the osquery extensions loaded by osquery, relative to the configuration file. osquery only loads extensions ending with `.ext`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extensions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 /// Represent field mapping and aply a specific modifer on the mapped field
 pub struct Map {
+    /// Change the field name from this value
     from: String,
+    /// Change the field name to this value
     to: String,
+    /// Keep the original field name with the new data after post-processing, default is `true`
     keep_original: Option<bool>,
+    /// Run post processing function on the value of this field
     modifier: Option<Modifier>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 /// Support artifact types:
 /// * Query: the artifact of this type conrespond to osquery SQL query(s)
@@ -59,22 +131,112 @@ pub enum ArtifactType {
     Command,
     Parse,
 }
-#[derive(Debug, Serialize, Deserialize)]
+impl Display for ArtifactType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtifactType::Query => f.write_str("query"),
+            ArtifactType::Collection => f.write_str("collection"),
+            ArtifactType::Command => f.write_str("command"),
+            ArtifactType::Parse => f.write_str("parse"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+/// The hashes of the files collected by `collection` artifacts, computed while the files are copied
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgorithm::Md5 => f.write_str("md5"),
+            HashAlgorithm::Sha1 => f.write_str("sha1"),
+            HashAlgorithm::Sha256 => f.write_str("sha256"),
+        }
+    }
+}
+
+/// The names accepted for the field `artifacts` of the artifacts
+pub(crate) const ARTIFACTS_ALIASES: [&str; 3] = ["queries", "paths", "commands"];
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 /// Contains artifact configuration such as type, maps, description, etc
 pub struct Artifact {
+    /// Name of the artifact, used as the file name of the output
     name: String,
+    /// The type of the artifact
     #[serde(rename(deserialize = "type"))]
     #[serde(rename = "type")]
     artifact_type: ArtifactType,
+    /// Description of the artifact, used only as a reference for the analyst
     description: Option<String>,
+    /// Timeout in seconds of the `command` artifact commands (default is `600`) or the `query` artifact queries (overrides `--timeout`)
     timeout: Option<u64>,
+    /// The osquery SQL queries of `query` artifacts, the paths of `collection` & `parse` artifacts or the commands of `command` artifacts
     #[serde(alias = "queries", alias = "paths", alias = "commands")]
     artifacts: Vec<String>,
+    /// Map field names to other names and modify their values (ex. change the field `creation_time` to `@timestamp`)
     maps: Option<Vec<Map>>,
+    /// Regular expression with named groups used by `parse` & `command` artifacts to output the data in structured format
     regex: Option<String>,
+    /// Tags used to select artifacts with `--tags` (ex. `volatile`)
+    tags: Option<Vec<String>>,
+    /// Conditions checked before running the artifact, the artifact is skipped if any of them is false
+    when: Option<Condition>,
+    /// Hashes of the files collected by `collection` artifacts added to the metadata records (default is `[md5, sha1, sha256]`, `[]` disables hashing)
+    hashes: Option<Vec<HashAlgorithm>>,
 }
 
 impl Artifact {
+    /// Checks if the artifact name matches any of the glob patterns
+    pub fn name_matches(&self, patterns: &[String]) -> bool {
+        patterns
+            .iter()
+            .any(|pattern| match glob::Pattern::new(pattern) {
+                Ok(pattern) => pattern.matches(&self.name),
+                Err(_) => pattern == &self.name,
+            })
+    }
+
+    /// Checks if the paths of the `collection` or `parse` artifact are expanded for every user, the results of these artifacts include the field `user`
+    pub fn uses_user_variables(&self) -> bool {
+        matches!(
+            self.artifact_type,
+            ArtifactType::Collection | ArtifactType::Parse
+        ) && self
+            .artifacts
+            .iter()
+            .any(|path| Variables::uses_users(path))
+    }
+
+    /// The hashes of the collected files, all the hashes are computed by default
+    pub fn hashes(&self) -> Vec<HashAlgorithm> {
+        let mut hashes = vec![];
+        for hash in self.hashes.as_deref().unwrap_or(&[
+            HashAlgorithm::Md5,
+            HashAlgorithm::Sha1,
+            HashAlgorithm::Sha256,
+        ]) {
+            if !hashes.contains(hash) {
+                hashes.push(*hash);
+            }
+        }
+        hashes
+    }
+
+    /// Checks if the artifact is tagged with any of the tags
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        match &self.tags {
+            Some(artifact_tags) => artifact_tags.iter().any(|tag| tags.contains(tag)),
+            None => false,
+        }
+    }
+
     pub fn map(&self, data: &Value) -> Option<Value> {
         match &self.artifact_type {
             ArtifactType::Query | ArtifactType::Command | ArtifactType::Parse => {
@@ -88,11 +250,8 @@ impl Artifact {
                                     if let Some(modifier) = &map.modifier {
                                         value = modifier.run(value);
                                         if let Some(false) = map.keep_original {
-                                            match new_data.remove(&map.from) {
-                                                Some(_) => {
-                                                    debug!("Removed the old field name '{}' for the artifact '{}' results", map.from, self.name);
-                                                }
-                                                None => {}
+                                            if new_data.remove(&map.from).is_some() {
+                                                debug!("Removed the old field name '{}' for the artifact '{}' results", map.from, self.name);
                                             }
                                         } else {
                                             new_data.insert(map.from.clone(), value.clone());
@@ -130,6 +289,9 @@ impl Default for Artifact {
             artifacts: vec![String::from("select * from users")],
             maps: None,
             regex: None,
+            tags: None,
+            when: None,
+            hashes: None,
         }
     }
 }
@@ -151,26 +313,57 @@ impl Display for OutputFormat {
     }
 }
 
+/// The paths matched by the glob patterns of a `collection` artifact with the user the pattern was expanded for,
+/// the errors of the expansion are added to the artifact report when it runs
+#[derive(Default)]
+struct CollectionListing {
+    paths: Vec<(PathBuf, Option<String>)>,
+    errors: Vec<String>,
+}
+
 // #[derive(Serialize, Deserialize)]
 /// Main struct responsable of parsing configuration, and running triage collection.
 pub struct Fennec<'a> {
     _config: FennecConfig,
     _osquery_binary_path: String,
+    _osquery_socket: Option<String>,
     _extension: OutputFormat,
-    _output_file: &'a mut ZipWriter<File>,
-    _foptions: FileOptions,
+    _output: Mutex<&'a mut dyn OutputSink>,
     _osquery_timeout: u64,
+    _osquery_retries: u32,
+    _osquery_flags: Vec<String>,
+    _osquery_extensions: Vec<String>,
     _file_collect_buf_size: usize,
+    _parallelism: usize,
+    _staging_limit: usize,
+    _include_artifacts: Vec<String>,
+    _exclude_artifacts: Vec<String>,
+    _tags: Vec<String>,
+    _config_sha256: String,
+    _config_files: Vec<ConfigFile>,
+    _config_overrides: Vec<String>,
+    _manifest: Mutex<Vec<ManifestEntry>>,
+    _started_at: DateTime<Utc>,
+    _read_limiter: Option<RateLimiter>,
+    _write_limiter: Option<RateLimiter>,
+    _max_package_size: Option<u64>,
+    _package_size: AtomicU64,
+    _available_space: Option<u64>,
+    _collections: Mutex<HashMap<String, CollectionListing>>,
+    _variables: Variables,
+    _typed_results: bool,
+    _column_types: ColumnTypes,
+    _native_tables: NativeTables,
 }
 
 impl<'a> Fennec<'a> {
-    /// Read configuration from YAML formated file and parses it then return an instance if this struct
+    /// Read configuration from YAML formated file and parses it then return an instance if this struct, the results are written to the output sink
     pub fn from_path(
         config_path: &str,
-        output_path: &'a mut ZipWriter<File>,
+        output: &'a mut dyn OutputSink,
     ) -> Result<Self, FennecError> {
-        let config_file = match File::open(config_path) {
-            Ok(f) => f,
+        let raw_config = match std::fs::read(config_path) {
+            Ok(raw_config) => raw_config,
             Err(e) => {
                 return Err(FennecError::config_error(format!(
                     "Can not read configuration file '{}', ERROR: {}",
@@ -178,37 +371,78 @@ impl<'a> Fennec<'a> {
                 )))
             }
         };
+        let base_dir = Path::new(config_path)
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
 
-        Self::from_reader(config_file, output_path)
+        Self::from_bytes(raw_config, config_path, base_dir, output)
     }
-    /// Same as `from_path` but it acceptes a reader insted of a file path
+    /// Same as `from_path` but it acceptes a reader insted of a file path, the `include` paths are relative to the current directory
     pub fn from_reader<R: Read>(
-        r: R,
-        output_path: &'a mut ZipWriter<File>,
+        mut r: R,
+        output: &'a mut dyn OutputSink,
     ) -> Result<Self, FennecError> {
-        let config: FennecConfig = match serde_yaml::from_reader(r) {
-            Ok(c) => c,
-            Err(e) => {
-                return Err(FennecError::config_error(format!(
-                    "Syntax error in the configuration, ERROR: {}",
-                    e
-                )))
-            }
-        };
+        let mut raw_config = vec![];
+        if let Err(e) = r.read_to_end(&mut raw_config) {
+            return Err(FennecError::config_error(format!(
+                "Can not read the configuration, ERROR: {}",
+                e
+            )));
+        }
+
+        Self::from_bytes(raw_config, "<reader>", Path::new("."), output)
+    }
 
-        let foptions = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    fn from_bytes(
+        raw_config: Vec<u8>,
+        source: &str,
+        base_dir: &Path,
+        output: &'a mut dyn OutputSink,
+    ) -> Result<Self, FennecError> {
+        let (config, config_files) = config::load(&raw_config, source, base_dir)?;
 
         Ok(Self {
             _config: config,
             _osquery_binary_path: String::from("/opt/osquery/bin/osqueryd"),
+            _osquery_socket: None,
             _extension: OutputFormat::JSONL,
-            _output_file: output_path,
-            _foptions: foptions,
+            _output: Mutex::new(output),
             _osquery_timeout: 10,
+            _osquery_retries: 2,
+            _osquery_flags: vec![],
+            _osquery_extensions: vec![],
             _file_collect_buf_size: 1024 * 1024 * 5,
+            _parallelism: 1,
+            _staging_limit: 1024 * 1024 * 64,
+            _include_artifacts: vec![],
+            _exclude_artifacts: vec![],
+            _tags: vec![],
+            _config_sha256: manifest::sha256(&raw_config),
+            _config_files: config_files,
+            _config_overrides: vec![],
+            _manifest: Mutex::new(vec![]),
+            _started_at: Utc::now(),
+            _read_limiter: None,
+            _write_limiter: None,
+            _max_package_size: None,
+            _package_size: AtomicU64::new(0),
+            _available_space: None,
+            _collections: Mutex::new(HashMap::new()),
+            _variables: Variables::default(),
+            _typed_results: true,
+            _column_types: ColumnTypes::default(),
+            _native_tables: NativeTables::default(),
         })
     }
 
+    /// Apply the configuration overrides (ex. `artifact.<name>.timeout=30`, `artifact.<name>.paths+=/opt/app/logs/*`) to the loaded artifacts,
+    /// the overrides are recorded in `collection_info.json`
+    pub fn apply_overrides(&mut self, overrides: &[String]) -> Result<(), FennecError> {
+        overrides::apply(&mut self._config, overrides)?;
+        self._config_overrides.extend_from_slice(overrides);
+        Ok(())
+    }
+
     /// This function is responsable of ouput formating specified in the configuration, for a list of supported output formats check `OutputFormat` enum.
     fn format(&self, data: &Value, artifact: &Artifact) -> String {
         match artifact.artifact_type {
@@ -218,30 +452,25 @@ impl<'a> Fennec<'a> {
             | ArtifactType::Collection => match self._extension {
                 OutputFormat::CSV => {
                     let mut writer = Writer::from_writer(vec![]);
-                    match data {
-                        Value::Object(obj) => {
-                            let values: Vec<String> = obj
-                                .values()
-                                .into_iter()
-                                .map(|a| match a {
-                                    Value::String(s) => s.clone(),
-                                    Value::Number(n) => n.to_string(),
-                                    Value::Bool(b) => b.to_string(),
-                                    Value::Null => String::from(""),
-                                    _ => format!("{}", a).to_string(),
-                                })
-                                .collect();
-                            match writer.write_record(values) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    debug!("Unable to write the data '{}' for the artifact '{}' to CSV writer, ERROR: '{}'", data, artifact.name, e);
-                                }
-                            };
-                        }
-                        _ => {}
+                    if let Value::Object(obj) = data {
+                        let values: Vec<String> = obj
+                            .values()
+                            .map(|a| match a {
+                                Value::String(s) => s.clone(),
+                                Value::Number(n) => n.to_string(),
+                                Value::Bool(b) => b.to_string(),
+                                Value::Null => String::from(""),
+                                _ => format!("{}", a),
+                            })
+                            .collect();
+                        match writer.write_record(values) {
+                            Ok(_) => {}
+                            Err(e) => {
+                                debug!("Unable to write the data '{}' for the artifact '{}' to CSV writer, ERROR: '{}'", data, artifact.name, e);
+                            }
+                        };
                     }
-                    let results = String::from_utf8(writer.into_inner().unwrap()).unwrap();
-                    results
+                    String::from_utf8(writer.into_inner().unwrap()).unwrap()
                 }
                 OutputFormat::JSONL => {
                     let results = match serde_json::to_string(data) {
@@ -279,12 +508,6 @@ impl<'a> Fennec<'a> {
         }
     }
 
-    /// Allows setting `FileOptions` of the ZipWriter at runtime.
-    pub fn set_options(mut self, foptions: &FileOptions) -> Self {
-        self._foptions = foptions.clone();
-        self
-    }
-
     /// Allows the setting of collection artifact buffer size at runtime, Default is `1024 * 1024 * 5 (5MiB)`
     pub fn set_collection_buf_size(mut self, size: usize) -> Self {
         self._file_collect_buf_size = size;
@@ -303,797 +526,1781 @@ impl<'a> Fennec<'a> {
         self
     }
 
+    /// Run the osquery SQL queries using the extension socket of a running osquery daemon instead of spawning osquery,
+    /// osquery is spawned if the daemon does not respond. Default is spawning osquery
+    pub fn set_osquery_socket(mut self, path: &str) -> Self {
+        self._osquery_socket = Some(String::from(path));
+        self
+    }
+
     /// Sets osquery queries timeout in seconds, Default `10`
     pub fn set_timeout(mut self, timeout: u64) -> Self {
         self._osquery_timeout = timeout;
         self
     }
 
-    /// Start triage collection from the artifacts specified in the configuration
-    pub fn triage(&mut self) -> Result<bool, FennecError> {
-        let mut process_osquery_artifacts = true;
-        let mut osquery_instance = OSQuery::new();
-        match OSQuery::new()
-            .spawn_instance(&self._osquery_binary_path)
-            .map_err(|e| {
-                FennecError::osquery_instance_error(format!(
-                    "Unable to create osquery instance '{}', ERROR: {}",
-                    &self._osquery_binary_path, e
-                ))
-            }) {
-            Ok(instance) => {
-                osquery_instance = instance.set_timeout(self._osquery_timeout);
-            }
-            Err(e) => {
-                error!("{}", e.message);
-                process_osquery_artifacts = false;
-                warn!(
-                    "Skiping artifact type '{:?}' duo to the previous error",
-                    ArtifactType::Query
-                );
+    /// Sets the number of times an osquery SQL query is retried when osquery crashes or stops answering, the osquery spawned by Fennec
+    /// is restarted before retrying if it does not answer and a query that timed out is retried with twice the timeout. Default `2`
+    pub fn set_osquery_retries(mut self, retries: u32) -> Self {
+        self._osquery_retries = retries;
+        self
+    }
+
+    /// Sets osquery flags added after the flags of the configuration section `osquery` (ex. `--disable_events`), Default no flags
+    pub fn set_osquery_flags(mut self, flags: Vec<String>) -> Self {
+        self._osquery_flags = flags;
+        self
+    }
+
+    /// Sets the paths of osquery extensions loaded in addition to the extensions of the configuration section `osquery`, Default no extensions
+    pub fn set_osquery_extensions(mut self, extensions: Vec<String>) -> Self {
+        self._osquery_extensions = extensions;
+        self
+    }
+
+    /// Sets the number of artifacts processed at the same time, Default `1`
+    pub fn set_parallelism(mut self, jobs: usize) -> Self {
+        self._parallelism = jobs.max(1);
+        self
+    }
+
+    /// Sets the size in bytes an artifact output can reach in memory before it is staged in a temporary file, Default `1024 * 1024 * 64 (64MiB)`
+    pub fn set_staging_limit(mut self, size: usize) -> Self {
+        self._staging_limit = size;
+        self
+    }
+
+    /// Only run the artifacts with names matching any of the glob patterns, Default is all artifacts
+    pub fn set_artifacts(mut self, patterns: Vec<String>) -> Self {
+        self._include_artifacts = patterns;
+        self
+    }
+
+    /// Skip the artifacts with names matching any of the glob patterns
+    pub fn set_excluded_artifacts(mut self, patterns: Vec<String>) -> Self {
+        self._exclude_artifacts = patterns;
+        self
+    }
+
+    /// Only run the artifacts tagged with any of the tags, Default is all artifacts
+    pub fn set_tags(mut self, tags: Vec<String>) -> Self {
+        self._tags = tags;
+        self
+    }
+
+    /// Output the osquery results using the types of the table columns (integers & doubles) instead of strings, Default is `true`
+    pub fn set_typed_results(mut self, typed_results: bool) -> Self {
+        self._typed_results = typed_results;
+        self
+    }
+
+    /// Limits the reads of the files collected by `collection` artifacts and parsed by `parse` artifacts to `bytes_per_second` for all the workers, Default is unlimited
+    pub fn set_read_rate(mut self, bytes_per_second: u64) -> Self {
+        self._read_limiter = Some(RateLimiter::new(bytes_per_second));
+        self
+    }
+
+    /// Limits the writes to the output to `bytes_per_second` before compression, Default is unlimited
+    pub fn set_write_rate(mut self, bytes_per_second: u64) -> Self {
+        self._write_limiter = Some(RateLimiter::new(bytes_per_second));
+        self
+    }
+
+    /// Sets the package size budget in bytes before compression, `collection` artifacts skip the files that do not fit in the remaining budget. Default is unlimited
+    pub fn set_max_package_size(mut self, size: u64) -> Self {
+        self._max_package_size = Some(size);
+        self
+    }
+
+    /// Sets the free space in bytes of the filesystem of the output, `triage` fails before running the artifacts if the files of the
+    /// `collection` artifacts (or the package size budget if it is smaller) do not fit. Default is not checked
+    pub fn set_available_space(mut self, size: u64) -> Self {
+        self._available_space = Some(size);
+        self
+    }
+
+    /// Reserve `size` bytes from the package size budget, returns false if the budget does not allow it
+    fn reserve(&self, size: u64) -> bool {
+        match self._max_package_size {
+            Some(max) => self
+                ._package_size
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                    used.checked_add(size).filter(|total| *total <= max)
+                })
+                .is_ok(),
+            None => {
+                self._package_size.fetch_add(size, Ordering::SeqCst);
+                true
             }
-        };
+        }
+    }
 
-        for artifact in self._config.artifacts.iter() {
-            let artifact = artifact.clone();
-            match artifact.artifact_type {
-                ArtifactType::Query => {
-                    if process_osquery_artifacts {
-                        match self._output_file.start_file(
-                            format!("{}.{}", artifact.name, self._extension),
-                            self._foptions,
-                        ) {
-                            Ok(_) => {
-                                info!(
-                                    "Start writing the results for the artifact '{}' to '{}'",
-                                    artifact.name,
-                                    format!("{}.{}", artifact.name, self._extension)
-                                );
-                            }
-                            Err(e) => {
-                                error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                            }
-                        };
-                        for sql in artifact.artifacts.iter() {
-                            info!(
-                                "Executing the osquery SQL query '{}' for the artifact '{}'",
-                                sql, artifact.name
-                            );
-                            match osquery_instance.query(sql.to_string()) {
-                                Ok(res) => {
-                                    match res.response {
-                                        Some(data) => {
-                                            let status = res.status.unwrap();
-                                            if status.code.unwrap().to_owned() == 0 {
-                                                let mut csv_headers_printed = false;
-                                                for row in data.iter() {
-                                                    let mut json = json!({});
-                                                    for (k, v) in row {
-                                                        match json {
-                                                            Value::Object(mut obj) => {
-                                                                obj.insert(
-                                                                    k.to_string(),
-                                                                    Value::String(v.to_string()),
-                                                                );
-                                                                json = Value::Object(obj);
-                                                            }
-                                                            _ => {}
-                                                        };
-                                                    }
-
-                                                    if let Some(data) = artifact.map(&json) {
-                                                        json = data;
-                                                    }
-
-                                                    if let OutputFormat::CSV = self._extension {
-                                                        if !csv_headers_printed {
-                                                            let headers: Vec<_> = json
-                                                                .as_object()
-                                                                .unwrap()
-                                                                .keys()
-                                                                .collect();
-                                                            let mut writer =
-                                                                Writer::from_writer(vec![]);
-                                                            writer.write_record(headers).unwrap();
-                                                            let data = String::from_utf8(
-                                                                writer.into_inner().unwrap(),
-                                                            )
-                                                            .unwrap();
-                                                            match self
-                                                                ._output_file
-                                                                .write(data.as_bytes())
-                                                            {
-                                                                Ok(_) => {
-                                                                    debug!("Wrote headers for the artifact '{}' to '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension));
-                                                                }
-                                                                Err(e) => {
-                                                                    error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                                }
-                                                            }
-                                                            csv_headers_printed = true
-                                                        }
-                                                    }
-
-                                                    let data = self.format(&json, &artifact);
-                                                    match self._output_file.write(data.as_bytes()) {
-                                                        Ok(n) => {
-                                                            debug!("Wrote '{}' bytes for the artifact '{}' to '{}'", n, artifact.name, format!("{}.{}",artifact.name,self._extension));
-                                                            if let Err(e) =
-                                                                self._output_file.flush()
-                                                            {
-                                                                error!("Unable to flush stream, ERROR: {}", e);
-                                                            };
-                                                        }
-                                                        Err(e) => {
-                                                            error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                        }
-                                                    }
-                                                }
-                                            } else if status.code.unwrap() == 1 {
-                                                error!(
-                                                    "Unable to execute osquery SQL query '{}', ERROR: '{}'",
-                                                    sql, status.message.unwrap()
-                                                );
-                                            }
-                                        }
-                                        None => {}
-                                    };
-                                }
-                                Err(error) => {
-                                    error!(
-                                        "Unable to execute osquery SQL query '{}', ERROR: {}",
-                                        sql, error
-                                    );
-                                }
-                            };
-                        }
-                    }
-                }
-                ArtifactType::Collection => {
-                    let mut files_metadata: Vec<String> = vec![];
-                    for path in artifact.artifacts.iter() {
-                        for entry in glob::glob(path).expect("Failed to read glob pattern") {
-                            match entry {
-                                Ok(entry) => {
-                                    let dest_path: PathBuf = entry
-                                        .as_path()
-                                        .components()
-                                        .collect::<Vec<_>>()
-                                        .iter()
-                                        .skip(1)
-                                        .collect();
-
-                                    let dest_path: PathBuf = [
-                                        artifact.name.clone(),
-                                        dest_path.as_path().to_string_lossy().to_string(),
-                                    ]
-                                    .iter()
-                                    .collect();
-
-                                    // Collect metadta for file/folder
-                                    match std::fs::metadata(entry.as_path()) {
-                                        Ok(metadata) => {
-                                            let mtime: DateTime<Utc> = metadata
-                                                .modified()
-                                                .unwrap_or(SystemTime::now())
-                                                .into();
-                                            let atime: DateTime<Utc> = metadata
-                                                .accessed()
-                                                .unwrap_or(SystemTime::now())
-                                                .into();
-                                            let ctime: DateTime<Utc> = metadata
-                                                .created()
-                                                .unwrap_or(SystemTime::now())
-                                                .into();
-                                            // let per: u32 = .unix().into();
-                                            let per = metadata.permissions().mode() & 0x1ff;
-                                            let file_type = match metadata.is_file() {
-                                                true => "file",
-                                                false => match metadata.is_dir() {
-                                                    true => "directory",
-                                                    false => "other",
-                                                },
-                                            };
-                                            let data = json!({
-                                                "full_path": entry.as_path().to_string_lossy(),
-                                                "type": file_type,
-                                                "size": metadata.size(),
-                                                "permessions": format!("{:03o}", per),
-                                                "owner_uid": metadata.uid(),
-                                                "owner_gid": metadata.gid(),
-                                                "mtime": mtime.format("%Y-%m-%d %H:%M:%S").to_string(),
-                                                "atime": atime.format("%Y-%m-%d %H:%M:%S").to_string(),
-                                                "ctime": ctime.format("%Y-%m-%d %H:%M:%S").to_string(),
+    /// Wrap the reader using the read rate limiter if it is set
+    fn throttled_reader<'b, R: Read + 'b>(&'b self, reader: R) -> Box<dyn Read + 'b> {
+        match &self._read_limiter {
+            Some(limiter) => Box::new(ThrottledReader::new(reader, limiter)),
+            None => Box::new(reader),
+        }
+    }
 
-                                            });
+    /// Wrap the writer using the write rate limiter if it is set
+    fn throttled_writer<'b, W: Write + 'b>(&'b self, writer: W) -> Box<dyn Write + 'b> {
+        match &self._write_limiter {
+            Some(limiter) => Box::new(ThrottledWriter::new(writer, limiter)),
+            None => Box::new(writer),
+        }
+    }
 
-                                            let data = self.format(&data, &artifact);
-                                            files_metadata.push(data);
-                                        }
-                                        Err(e) => {
-                                            error!("Unable to collect metadata for '{}' for the artifact '{}', ERROR: '{}'", entry.as_path().to_string_lossy(), artifact.name, e);
-                                        }
-                                    }
+    /// Checks if the artifact is selected by the names, excluded names and tags filters
+    fn is_selected(&self, artifact: &Artifact) -> bool {
+        (self._include_artifacts.is_empty() || artifact.name_matches(&self._include_artifacts))
+            && (self._tags.is_empty() || artifact.has_any_tag(&self._tags))
+            && !artifact.name_matches(&self._exclude_artifacts)
+    }
 
-                                    if entry.as_path().is_dir() {
-                                        match self._output_file.add_directory(
-                                            dest_path.as_path().to_string_lossy(),
-                                            self._foptions,
-                                        ) {
-                                            Ok(_) => {
-                                                debug!("Created directory entry for '{}' in ZIP file for artifact '{}' successfully!", dest_path.as_path().to_string_lossy(), artifact.name);
-                                            }
-                                            Err(e) => {
-                                                error!("Unable to create directory entry '{}' in ZIP file for the artifact '{}', ERROR: '{}'",dest_path.as_path().to_string_lossy(), artifact.name, e);
-                                            }
-                                        }
-                                    } else {
-                                        match self._output_file.start_file(
-                                            dest_path.as_path().to_string_lossy(),
-                                            self._foptions,
-                                        ) {
-                                            Ok(_) => {
-                                                debug!("Created file entry for '{}' in ZIP file for artifact '{}' successfully!", dest_path.as_path().to_string_lossy(), artifact.name);
-                                            }
-                                            Err(e) => {
-                                                error!("Unable to create file entry '{}' in ZIP file for the artifact '{}', ERROR: '{}'",dest_path.as_path().to_string_lossy(), artifact.name, e);
-                                            }
-                                        }
+    /// Returns the artifacts selected by the names, excluded names and tags filters
+    fn selected_artifacts(&self) -> Vec<&Artifact> {
+        self._config
+            .artifacts
+            .iter()
+            .filter(|artifact| {
+                let selected = self.is_selected(artifact);
+                if !selected {
+                    debug!(
+                        "The artifact '{}' is not selected by the artifacts filters, skipping it",
+                        artifact.name
+                    );
+                }
+                selected
+            })
+            .collect()
+    }
 
-                                        info!(
-                                            "Copying the file '{}' for the artifact '{}'",
-                                            entry.as_path().to_string_lossy(),
-                                            artifact.name
-                                        );
-
-                                        match File::open(entry.as_path()) {
-                                            Ok(in_file) => {
-                                                let mut reader = BufReader::with_capacity(
-                                                    self._file_collect_buf_size,
-                                                    in_file,
-                                                );
-
-                                                loop {
-                                                    let buf = reader.fill_buf().unwrap();
-                                                    if buf.len() == 0 {
-                                                        debug!("Finished writing the file '{}' for the artifact '{}' to the ZIP file", dest_path.as_path().to_string_lossy(), artifact.name);
-                                                        break;
-                                                    }
-                                                    let bytes =
-                                                        self._output_file.write(&buf).unwrap();
-                                                    debug!("Wrote '{}' bytes for the artifact '{}' to '{}' successfuly!", bytes, artifact.name, dest_path.as_path().to_string_lossy());
-                                                    reader.consume(bytes);
-                                                }
-                                            }
-                                            Err(e) => {
-                                                error!("Unable to open the file '{}' for the artifact '{}', ERROR: '{}'", entry.as_path().to_string_lossy(), artifact.name, e);
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(e) => error!(
-                                    "Error in glob for the artifact '{}', ERROR: '{:?}'",
-                                    artifact.name, e
-                                ),
-                            }
-                        }
-                    }
-                    match self._output_file.start_file(
-                        format!(
-                            "{}/{}_metadata.{}",
-                            artifact.name, artifact.name, self._extension
-                        ),
-                        self._foptions,
-                    ) {
-                        Ok(_) => {
-                            if let OutputFormat::CSV = self._extension {
-                                let mut writer = Writer::from_writer(vec![]);
-                                writer
-                                    .write_record(vec![
-                                        "full_path",
-                                        "file_type",
-                                        "size",
-                                        "permessions",
-                                        "owner_uid",
-                                        "owner_gid",
-                                        "mtime",
-                                        "atime",
-                                        "ctime",
-                                    ])
-                                    .unwrap();
-                                let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
-                                match self._output_file.write(data.as_bytes()) {
-                                    Ok(_) => {
-                                        debug!(
-                                            "Wrote headers for the artifact '{}' to '{}'",
-                                            artifact.name,
-                                            format!("{}.{}", artifact.name, self._extension)
-                                        );
-                                        if let Err(e) = self._output_file.flush() {
-                                            error!("Unable to flush stream, ERROR: {}", e);
-                                        };
-                                    }
-                                    Err(e) => {
-                                        error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                    }
-                                }
-                            }
+    /// Resolve what each artifact would collect without running it. The filters and the conditions that do not require osquery are applied,
+    /// and the glob patterns of `collection` & `parse` artifacts are expanded to the files they match
+    pub fn plan(&self) -> TriagePlan {
+        let variables = Variables::load(None);
+        let mut artifacts = vec![];
+        for artifact in self._config.artifacts.iter() {
+            let mut plan = ArtifactPlan {
+                name: artifact.name.clone(),
+                artifact_type: artifact.artifact_type.to_string(),
+                status: PlanStatus::Run,
+                reason: None,
+                artifacts: artifact.artifacts.clone(),
+                timeout: artifact.timeout,
+                files: vec![],
+                estimated_size: 0,
+            };
 
-                            for line in files_metadata.iter() {
-                                self._output_file.write(line.as_bytes()).unwrap();
-                                if let Err(e) = self._output_file.flush() {
-                                    error!("Unable to flush stream, ERROR: {}", e);
-                                };
-                            }
-                        }
-                        Err(e) => {
-                            error!("Unable to write the metadata for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}_metadata.{}",artifact.name,self._extension), e);
+            if !self.is_selected(artifact) {
+                plan.status = PlanStatus::Filtered;
+                plan.reason = Some(String::from(
+                    "not selected by the names, excluded names or tags filters",
+                ));
+            } else if let Some(condition) = &artifact.when {
+                match condition.evaluate_local() {
+                    Ok(_) => {
+                        if let Some(sql) = condition.query() {
+                            plan.reason = Some(format!(
+                                "runs only if the osquery SQL query '{}' returns rows",
+                                sql
+                            ));
                         }
                     }
+                    Err(reason) => {
+                        plan.status = PlanStatus::Skipped;
+                        plan.reason = Some(reason);
+                    }
                 }
-                ArtifactType::Command => {
-                    match self._output_file.start_file(
-                        format!("{}.{}", artifact.name, self._extension),
-                        self._foptions,
-                    ) {
-                        Ok(_) => {
-                            info!(
-                                "Start writing the results for the artifact '{}' to '{}'",
-                                artifact.name,
-                                format!("{}.{}", artifact.name, self._extension)
-                            );
-                        }
-                        Err(e) => {
-                            error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                        }
-                    };
+            }
 
-                    let shell = match std::env::var_os("SHELL") {
-                        Some(env) => env.to_string_lossy().to_string(),
-                        None => {
-                            if Path::new("/bin/bash").exists() {
-                                String::from("/bin/bash")
-                            } else if Path::new("/bin/sh").exists() {
-                                String::from("/bin/sh")
-                            } else {
-                                error!("Unable to find shell ($SHELL, /bin/bash and /bin/sh not found), Skipping command execution");
+            if plan.status == PlanStatus::Run {
+                if let ArtifactType::Collection | ArtifactType::Parse = artifact.artifact_type {
+                    for path in artifact.artifacts.iter() {
+                        let patterns = match variables.expand(path) {
+                            Ok(patterns) => patterns,
+                            Err(e) => {
+                                error!(
+                                    "Unable to expand the variables in the path '{}' for the artifact '{}', ERROR: {}",
+                                    path, artifact.name, e
+                                );
                                 continue;
                             }
-                        }
-                    };
-                    for command in artifact.artifacts.iter() {
-                        let mut child = Command::new(&shell)
-                            .arg("-c")
-                            .arg(command)
-                            .stdout(Stdio::piped())
-                            .stderr(Stdio::piped())
-                            .spawn()
-                            .unwrap();
-                        let pid = child.id();
-                        let timeout_in = match artifact.timeout {
-                            Some(timeout) => {
-                                info!("Started the command '{}' for the artifact '{}' with timeout of '{}' seconds. PID: {}", command, artifact.name, timeout, pid);
-                                Duration::from_secs(timeout)
-                            }
-                            None => {
-                                info!("Started the command '{}' for the artifact '{}' without timeout. Setting timout to '600' seconds. Make sure to set timeout for all command artifacts to aviod command taking unresonable time to finish. PID: {}", command, artifact.name, pid);
-                                Duration::from_secs(600)
-                            }
                         };
-                        let started_execution = Instant::now();
-
-                        let mut counter = 0;
-                        let mut csv_headers_printed = false;
-
-                        // Keep reading stdout until the process exits or the process is killed due to timeout
-                        loop {
-                            match child.stdout.as_mut() {
-                                Some(stdout) => {
-                                    let mut csv_headers =
-                                        vec!["line".to_string(), "stdout/stderr".to_string()];
-                                    match stdout.read_until(0xA) {
-                                        Ok(line) => {
-                                            if !line.is_empty() {
-                                                let mut row: Value = json!({});
-                                                match &artifact.regex {
-                                                    Some(regex) => {
-                                                        if let Ok(re) = Regex::new(regex) {
-                                                            if let Some(groups) = re.captures(&line)
-                                                            {
-                                                                let mut data: serde_json::Map<
-                                                                    String,
-                                                                    Value,
-                                                                > = serde_json::Map::new();
-                                                                re.capture_names().for_each(|name| {
-                                                                    if let Some(name) = name {
-                                                                        let value = match groups.name(name) {
-                                                                            Some(m) => Value::String(
-                                                                                m.as_str().to_string(),
-                                                                            ),
-                                                                            None => Value::Null,
-                                                                        };
-                                                                        data.insert(name.to_string(), value);
-                                                                    }
-                                                                });
-
-                                                                let mut json =
-                                                                    Value::Object(data.clone());
-
-                                                                if let Some(data) =
-                                                                    artifact.map(&json)
-                                                                {
-                                                                    json = data;
-                                                                }
-                                                                row = json;
-                                                            } else {
-                                                                error!("Unable to parse the line '{}' for the artifact '{}'", line, artifact.name);
-                                                            }
-                                                        }
-                                                    }
-                                                    None => {
-                                                        row = json!({
-                                                            "line": counter,
-                                                            "stdout": line
-                                                        });
-
-                                                        if let Some(data) = artifact.map(&row) {
-                                                            row = data;
-                                                        }
-                                                    }
-                                                }
-
-                                                if let OutputFormat::CSV = self._extension {
-                                                    if !csv_headers_printed {
-                                                        if let Some(_) = &artifact.regex {
-                                                            csv_headers = row
-                                                                .as_object()
-                                                                .unwrap()
-                                                                .keys()
-                                                                .map(|s| s.to_owned())
-                                                                .collect::<Vec<String>>();
-                                                        }
-                                                        let mut writer =
-                                                            Writer::from_writer(vec![]);
-                                                        writer.write_record(&csv_headers).unwrap();
-                                                        let data = String::from_utf8(
-                                                            writer.into_inner().unwrap(),
-                                                        )
-                                                        .unwrap();
-                                                        match self
-                                                            ._output_file
-                                                            .write(data.as_bytes())
-                                                        {
-                                                            Ok(_) => {
-                                                                debug!(
-                                                                        "Wrote headers for the artifact '{}' to '{}'",
-                                                                        artifact.name,
-                                                                        format!(
-                                                                            "{}.{}",
-                                                                            artifact.name, self._extension
-                                                                        )
-                                                                    );
-                                                                if let Err(e) =
-                                                                    self._output_file.flush()
-                                                                {
-                                                                    error!(
-                                                                        "Unable to flush stream, ERROR: {}",
-                                                                        e
-                                                                    );
-                                                                };
-                                                            }
-                                                            Err(e) => {
-                                                                error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                            }
-                                                        }
-                                                        csv_headers_printed = true
-                                                    }
-                                                }
-
-                                                let data = self.format(&row, &artifact);
-                                                match self._output_file.write(data.as_bytes()) {
-                                                    Ok(n) => {
-                                                        debug!(
-                                                            "Wrote '{}' bytes for the artifact '{}' to '{}'",
-                                                            n,
-                                                            artifact.name,
-                                                            format!("{}.{}", artifact.name, self._extension)
-                                                        );
-                                                        if let Err(e) = self._output_file.flush() {
-                                                            error!(
-                                                                "Unable to flush stream, ERROR: {}",
-                                                                e
-                                                            );
-                                                        };
-                                                    }
-                                                    Err(e) => {
-                                                        error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                    }
-                                                }
-                                                counter += 1;
-                                            }
-                                        }
-                                        Err(_) => match child.try_wait() {
-                                            Ok(Some(status)) => {
-                                                let status_code = match status.code() {
-                                                    Some(code) => format!("{code}"),
-                                                    None => String::from("UNKNOWN"),
-                                                };
-                                                info!(
-                                                        "Process for the command '{}' and PID '{}' exited with the status: {}, finished writing the results", 
-                                                        command,
-                                                        pid,
-                                                        status_code
-                                                        );
-                                                break;
-                                            }
-                                            Ok(None) => {
-                                                debug!("Process with the PID '{pid}' still running, keep reading stdout");
-                                            }
-                                            Err(e) => {
-                                                error!("Error retriving process status for the process with the PID: {pid}, ERROR: {e}");
-                                            }
-                                        },
-                                    }
-                                }
-                                None => {
-                                    error!("Unable to retrive stdout stream for the command '{}' for the artifact '{}'", command, artifact.name);
+                        for Expanded { pattern, .. } in patterns {
+                            let paths = match glob::glob(&pattern) {
+                                Ok(paths) => paths,
+                                Err(e) => {
+                                    error!(
+                                        "Invalid glob pattern '{}' for the artifact '{}', ERROR: '{}'",
+                                        pattern, artifact.name, e
+                                    );
+                                    continue;
                                 }
-                            }
-
-                            if started_execution.elapsed() >= timeout_in {
-                                match child.kill() {
-                                    Ok(_) => match child.wait() {
-                                        Ok(status) => {
-                                            let code = match status.code() {
-                                                Some(scode) => format!("{scode}"),
-                                                None => String::from("UNKNOWN"),
-                                            };
-                                            info!("Process for the command '{}' (PID: {}) for the artifact '{}' killed due to timeout, Status: {}",command, pid, artifact.name, code);
-                                        }
-                                        Err(e) => {
-                                            warn!("Unable to kill the process for the command '{}' (PID: {}) for the artifact '{}', ERROR: {}",command, pid, artifact.name, e);
+                            };
+                            for entry in paths.flatten() {
+                                if let Ok(metadata) = std::fs::metadata(&entry) {
+                                    if metadata.is_file() {
+                                        // The parsed files are not copied to the package
+                                        if let ArtifactType::Collection = artifact.artifact_type {
+                                            plan.estimated_size += metadata.size();
                                         }
-                                    },
-                                    Err(e) => {
-                                        warn!("Unable to kill the process for the command '{}' (PID: {}) for the artifact '{}', ERROR: {}",command, pid, artifact.name, e);
+                                        plan.files.push(PlannedFile {
+                                            path: entry.to_string_lossy().to_string(),
+                                            size: metadata.size(),
+                                        });
                                     }
                                 }
-                                break;
                             }
                         }
+                    }
+                }
+            }
+            artifacts.push(plan);
+        }
 
-                        counter = 0;
-
-                        // Keep reading stderr until the process exits or the process is killed due to timeout
-                        loop {
-                            match child.stderr.as_mut() {
-                                Some(stderr) => match stderr.read_until(0xA) {
-                                    Ok(line) => {
-                                        if !line.is_empty() {
-                                            let mut row = json!({
-                                                "line": counter,
-                                                "stderr": line
-                                            });
+        let estimated_size = artifacts.iter().map(|a| a.estimated_size).sum();
+        TriagePlan {
+            artifacts,
+            estimated_size,
+        }
+    }
 
-                                            if let Some(data) = artifact.map(&row) {
-                                                row = data;
-                                            }
+    /// Lock the output file, the lock is recovered if another worker panicked while holding it
+    fn output(&self) -> MutexGuard<'_, &'a mut dyn OutputSink> {
+        match self._output.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
 
-                                            let data = self.format(&row, &artifact);
-                                            match self._output_file.write(data.as_bytes()) {
-                                                Ok(n) => {
-                                                    debug!(
-                                                        "Wrote '{}' bytes for the artifact '{}' to '{}'",
-                                                        n,
-                                                        artifact.name,
-                                                        format!("{}.{}", artifact.name, self._extension)
-                                                    );
-                                                    if let Err(e) = self._output_file.flush() {
-                                                        error!(
-                                                            "Unable to flush stream, ERROR: {}",
-                                                            e
-                                                        );
-                                                    };
-                                                }
-                                                Err(e) => {
-                                                    error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                }
-                                            }
-                                            counter += 1;
-                                        }
-                                    }
-                                    Err(_) => {
-                                        debug!("Done reading the stderr for the command '{}' (PID: {}) for the artifact '{}'", command, pid, artifact.name);
-                                        break;
-                                    }
-                                },
-                                None => {
-                                    error!("Unable to retrive stderr stream for the command '{}' for the artifact '{}'", command, artifact.name);
-                                }
-                            }
-                        }
-                    }
-                }
-                ArtifactType::Parse => {
-                    match self._output_file.start_file(
-                        format!("{}.{}", artifact.name, self._extension),
-                        self._foptions,
-                    ) {
-                        Ok(_) => {
-                            info!(
-                                "Start writing the results for the artifact '{}' to '{}'",
-                                artifact.name,
-                                format!("{}.{}", artifact.name, self._extension)
-                            );
-                        }
-                        Err(e) => {
-                            error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                            continue;
-                        }
-                    };
-                    for path in artifact.artifacts.iter() {
-                        for entry in glob::glob(path).expect("Failed to read glob pattern") {
-                            match entry {
-                                Ok(entry) => {
-                                    if !entry.as_path().is_dir() {
-                                        info!(
-                                            "Parsing the file '{}' for the artifact '{}'",
-                                            entry.as_path().to_string_lossy(),
-                                            artifact.name
-                                        );
-                                        match File::open(entry.as_path()) {
-                                            Ok(in_file) => {
-                                                if let Some(regex) = &artifact.regex {
-                                                    if let Ok(re) = Regex::new(regex.as_str()) {
-                                                        let reader = match entry
-                                                            .as_path()
-                                                            .to_string_lossy()
-                                                            .ends_with(".gz")
-                                                        {
-                                                            true => Box::new(BufReader::new(
-                                                                GzDecoder::new(in_file),
-                                                            ))
-                                                                as Box<dyn BufRead>,
-                                                            false => {
-                                                                Box::new(BufReader::new(in_file))
-                                                                    as Box<dyn BufRead>
-                                                            }
-                                                        };
-                                                        let mut csv_headers_printed = false;
-                                                        for line in reader.lines() {
-                                                            if let Err(e) = &line {
-                                                                error!("A line in the file '{}' is not a string, ERROR: {}", entry.as_path().to_string_lossy(), e);
-                                                                continue;
-                                                            }
-                                                            let line = &line.unwrap();
-                                                            if let Some(groups) = re.captures(line)
-                                                            {
-                                                                let mut data: serde_json::Map<
-                                                                    String,
-                                                                    Value,
-                                                                > = serde_json::Map::new();
-
-                                                                re.capture_names().for_each(|name| {
-                                                                    if let Some(name) = name {
-                                                                        let value = match groups
-                                                                            .name(name)
-                                                                        {
-                                                                            Some(m) => Value::String(
-                                                                                m.as_str().to_string(),
-                                                                            ),
-                                                                            None => Value::Null,
-                                                                        };
-                                                                        data.insert(
-                                                                            name.to_string(),
-                                                                            value,
-                                                                        );
-                                                                    }
-                                                                });
-
-                                                                data.insert(
-                                                                    "full_path".to_string(),
-                                                                    Value::String(
-                                                                        entry
-                                                                            .as_path()
-                                                                            .to_string_lossy()
-                                                                            .to_string(),
-                                                                    ),
-                                                                );
-
-                                                                let mut json =
-                                                                    Value::Object(data.clone());
-
-                                                                if let Some(data) =
-                                                                    artifact.map(&json)
-                                                                {
-                                                                    json = data;
-                                                                }
-
-                                                                data = json
-                                                                    .as_object()
-                                                                    .unwrap()
-                                                                    .clone();
-
-                                                                if let OutputFormat::CSV =
-                                                                    self._extension
-                                                                {
-                                                                    if !csv_headers_printed {
-                                                                        let headers: Vec<_> =
-                                                                            data.keys().collect();
-                                                                        let mut writer =
-                                                                            Writer::from_writer(
-                                                                                vec![],
-                                                                            );
-                                                                        writer
-                                                                            .write_record(headers)
-                                                                            .unwrap();
-                                                                        let data =
-                                                                            String::from_utf8(
-                                                                                writer
-                                                                                    .into_inner()
-                                                                                    .unwrap(),
-                                                                            )
-                                                                            .unwrap();
-                                                                        match self
-                                                                            ._output_file
-                                                                            .write(data.as_bytes())
-                                                                        {
-                                                                            Ok(_) => {
-                                                                                debug!("Wrote headers for the artifact '{}' to '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension));
-                                                                            }
-                                                                            Err(e) => {
-                                                                                error!("Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'", artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                                            }
-                                                                        }
-                                                                        csv_headers_printed = true
-                                                                    }
-                                                                }
-
-                                                                let data =
-                                                                    self.format(&json, &artifact);
-                                                                match self
-                                                                    ._output_file
-                                                                    .write(data.as_bytes())
-                                                                {
-                                                                    Ok(n) => {
-                                                                        if let Ok(_) = self
-                                                                            ._output_file
-                                                                            .flush()
-                                                                        {
-                                                                            debug!("Wrote '{}' bytes for the file '{}' for the artifact '{}' to '{}'", n, entry.as_path().to_string_lossy(), artifact.name, format!("{}.{}",artifact.name,self._extension));
-                                                                        }
-                                                                    }
-                                                                    Err(e) => {
-                                                                        error!("Unable to write the results for the file '{}' for the artifact '{}' to '{}', ERROR: '{}'", entry.as_path().to_string_lossy(), artifact.name, format!("{}.{}",artifact.name,self._extension), e);
-                                                                    }
-                                                                }
-                                                            } else {
-                                                                error!("Unable to parse the line '{}' for the artifact '{}'", line, artifact.name);
-                                                            }
-                                                        }
-                                                    } else {
-                                                        error!("Unable to parse the regulare expretion for the artifact '{}'", artifact.name);
-                                                    }
-                                                } else {
-                                                    error!("The artifact '{}' has the artifact type 'parse' which regires the field 'regex'", artifact.name);
-                                                }
-                                            }
-                                            Err(e) => {
-                                                error!("Unable to open the file '{}' for the artifact '{}', ERROR: '{}'", entry.as_path().to_string_lossy(), artifact.name, e);
-                                            }
-                                        };
-                                    }
-                                }
-                                Err(e) => error!(
-                                    "Error in glob for the artifact '{}', ERROR: '{:?}'",
-                                    artifact.name, e
-                                ),
-                            }
-                        }
-                    }
+    /// Write a record to the staged entry of an artifact, CSV headers are written before the first record
+    fn write_record(
+        &self,
+        entry: &mut StagedEntry,
+        artifact: &Artifact,
+        record: &Value,
+        report: &mut ArtifactReport,
+    ) {
+        if let OutputFormat::CSV = self._extension {
+            if !entry.csv_headers_printed {
+                if entry.csv_headers.is_none() {
+                    entry.csv_headers = record
+                        .as_object()
+                        .map(|obj| obj.keys().map(|k| k.to_owned()).collect());
                 }
-            };
+                self.write_csv_headers(entry, artifact, report);
+            }
+        }
+
+        let data = self.format(record, artifact);
+        match entry.data.write_all(data.as_bytes()) {
+            Ok(_) => {
+                trace!(
+                    "Wrote '{}' bytes for the artifact '{}' to '{}'",
+                    data.len(),
+                    artifact.name,
+                    entry.name
+                );
+                report.rows += 1;
+            }
+            Err(e) => {
+                report.error(format!(
+                    "Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'",
+                    artifact.name, entry.name, e
+                ));
+            }
         }
-        Ok(true)
+    }
+
+    /// Write the data from the reader to a new entry in the output file, the entry is recorded in the manifest with its SHA-256
+    fn write_entry(
+        &self,
+        name: &str,
+        reader: &mut dyn Read,
+        source_path: Option<&Path>,
+        artifact: Option<&Artifact>,
+    ) -> Result<u64, std::io::Error> {
+        self.write_hashed_entry(name, reader, None, source_path, artifact, &[])
+            .map(|(size, _, _)| size)
+    }
+
+    /// Same as `write_entry` but also computes the hashes of the data in the same pass, the SHA-256 is always computed for the manifest.
+    /// `size` is the size of the data when it is known before reading it. When the output streams the entry (ex. the large files of the
+    /// tar output) exactly `size` bytes are written, the data after `size` is not written and the entry is padded with zeros if the reader
+    /// ends before `size`, the returned message describes the change
+    fn write_hashed_entry(
+        &self,
+        name: &str,
+        reader: &mut dyn Read,
+        size: Option<u64>,
+        source_path: Option<&Path>,
+        artifact: Option<&Artifact>,
+        hashes: &[HashAlgorithm],
+    ) -> Result<(u64, Hashes, Option<String>), std::io::Error> {
+        let mut output = self.output();
+        match size {
+            Some(size) => output.start_sized_entry(name, size)?,
+            None => output.start_entry(name)?,
+        }
+        let streamed_size = size.filter(|_| output.is_streaming());
+        let mut writer = HashingWriter::new(self.throttled_writer(&mut **output)).with_hashes(
+            hashes.contains(&HashAlgorithm::Md5),
+            hashes.contains(&HashAlgorithm::Sha1),
+        );
+        let mut changed = None;
+        match streamed_size {
+            Some(size) => {
+                let copied = std::io::copy(&mut reader.take(size), &mut writer)?;
+                if copied < size {
+                    std::io::copy(&mut std::io::repeat(0).take(size - copied), &mut writer)?;
+                    changed = Some(format!(
+                        "the data shrank from '{}' to '{}' bytes while it was written, the entry '{}' is padded with zeros",
+                        size, copied, name
+                    ));
+                } else if reader.read(&mut [0; 1])? > 0 {
+                    changed = Some(format!(
+                        "the data grew after '{}' bytes while it was written, the entry '{}' is truncated",
+                        size, name
+                    ));
+                }
+            }
+            None => {
+                std::io::copy(reader, &mut writer)?;
+            }
+        }
+        let (size, hashes) = writer.finalize_hashes();
+        output.end_entry()?;
+        self.record(ManifestEntry {
+            name: name.to_string(),
+            size,
+            sha256: Some(hashes.sha256.clone()),
+            source_path: source_path.map(|p| p.to_string_lossy().to_string()),
+            artifact: artifact.map(|a| a.name.clone()),
+        });
+        Ok((size, hashes, changed))
+    }
+
+    /// Add an entry to the package manifest
+    fn record(&self, entry: ManifestEntry) {
+        match self._manifest.lock() {
+            Ok(mut manifest) => manifest.push(entry),
+            Err(poisoned) => poisoned.into_inner().push(entry),
+        }
+    }
+
+    /// Write a staged entry to the output file
+    fn commit_entry(&self, artifact: &Artifact, entry: StagedEntry, report: &mut ArtifactReport) {
+        let size = entry.data.len();
+        let mut reader = match entry.data.into_reader() {
+            Ok(reader) => reader,
+            Err(e) => {
+                report.error(format!(
+                    "Unable to read the staged results for the artifact '{}', ERROR: '{}'",
+                    artifact.name, e
+                ));
+                return;
+            }
+        };
+        info!(
+            "Start writing the results for the artifact '{}' to '{}'",
+            artifact.name, entry.name
+        );
+        // The size of the staged data is known so the output can stream it or start a new volume for it
+        match self.write_hashed_entry(
+            &entry.name,
+            &mut reader,
+            Some(size),
+            None,
+            Some(artifact),
+            &[],
+        ) {
+            Ok((n, _, _)) => {
+                debug!(
+                    "Wrote '{}' of '{}' bytes for the artifact '{}' to '{}'",
+                    n, size, artifact.name, entry.name
+                );
+                report.bytes_written += n;
+                // The results of the artifacts are always written but they count towards the package size budget
+                self._package_size.fetch_add(n, Ordering::SeqCst);
+            }
+            Err(e) => {
+                report.error(format!(
+                    "Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'",
+                    artifact.name, entry.name, e
+                ));
+            }
+        }
+    }
+
+    /// Copy the file in `path` to the output file with the entry name `name`. Used to add files that are not produced by artifacts such as the log file
+    pub fn add_file(&self, path: &str, name: &str) -> Result<u64, FennecError> {
+        let mut in_file = File::open(path).map_err(|e| {
+            FennecError::output_error(format!("Unable to open the file '{}', ERROR: {}", path, e))
+        })?;
+        self.write_entry(name, &mut in_file, Some(Path::new(path)), None)
+            .map_err(|e| {
+                FennecError::output_error(format!(
+                    "Unable to write the file '{}' to '{}', ERROR: {}",
+                    path, name, e
+                ))
+            })
+    }
+
+    /// Write `data` to the output file with the entry name `name`. Used to add data that is not in a file such as the log kept in memory
+    pub fn add_data(&self, name: &str, data: &[u8]) -> Result<u64, FennecError> {
+        self.write_hashed_entry(
+            name,
+            &mut &data[..],
+            Some(data.len() as u64),
+            None,
+            None,
+            &[],
+        )
+        .map(|(size, _, _)| size)
+        .map_err(|e| {
+            FennecError::output_error(format!(
+                "Unable to write the data to '{}', ERROR: {}",
+                name, e
+            ))
+        })
+    }
+
+    /// Write `manifest.json` which lists every entry in the output file with its SHA-256, and `collection_info.json` which contains chain of custody information.
+    /// This should be called after all the files are added to the output file
+    pub fn write_manifest(&self) -> Result<(), FennecError> {
+        let manifest = {
+            let entries = match self._manifest.lock() {
+                Ok(entries) => entries,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            serde_json::to_vec_pretty(&*entries).map_err(|e| {
+                FennecError::output_error(format!("Unable to serialize the manifest, ERROR: {}", e))
+            })?
+        };
+        let mut output = self.output();
+        output
+            .start_sized_entry("manifest.json", manifest.len() as u64)
+            .and_then(|_| output.write_all(&manifest))
+            .and_then(|_| output.end_entry())
+            .map_err(|e| {
+                FennecError::output_error(format!("Unable to write 'manifest.json', ERROR: {}", e))
+            })?;
+
+        let hostname = match hostname::get() {
+            Ok(name) => name.to_string_lossy().to_string(),
+            Err(_) => String::from("HOSTNAME_NOT_FOUND"),
+        };
+        let collection_info = CollectionInfo {
+            hostname,
+            fennec_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_sha256: self._config_sha256.clone(),
+            config_files: self._config_files.clone(),
+            config_overrides: self._config_overrides.clone(),
+            operator_uid: nix::unistd::getuid().as_raw(),
+            effective_uid: nix::unistd::geteuid().as_raw(),
+            sudo_user: std::env::var("SUDO_USER").ok(),
+            started_at: self._started_at.to_rfc3339(),
+            finished_at: Utc::now().to_rfc3339(),
+            timezone: Local::now().offset().to_string(),
+            manifest_sha256: manifest::sha256(&manifest),
+        };
+        let collection_info = serde_json::to_vec_pretty(&collection_info).map_err(|e| {
+            FennecError::output_error(format!(
+                "Unable to serialize the collection info, ERROR: {}",
+                e
+            ))
+        })?;
+        output
+            .start_sized_entry("collection_info.json", collection_info.len() as u64)
+            .and_then(|_| output.write_all(&collection_info))
+            .and_then(|_| output.end_entry())
+            .map_err(|e| {
+                FennecError::output_error(format!(
+                    "Unable to write 'collection_info.json', ERROR: {}",
+                    e
+                ))
+            })?;
+        info!("Wrote 'manifest.json' & 'collection_info.json' to the output file");
+        Ok(())
+    }
+
+    /// Start triage collection from the artifacts specified in the configuration, the returned report is also written to the output file as `triage_report.json`
+    pub fn triage(&mut self) -> Result<TriageReport, FennecError> {
+        self._started_at = Utc::now();
+        let started = Instant::now();
+        let mut errors = vec![];
+        let osquery = OSQuerySupervisor::new(
+            &self._osquery_binary_path,
+            self._osquery_timeout,
+            self.osquery_flags(),
+            self.osquery_extensions(),
+            self.attach_osquery(),
+        );
+        if let Err(e) = osquery.start().map_err(|e| {
+            FennecError::osquery_instance_error(format!(
+                "Unable to create osquery instance '{}', ERROR: {}",
+                &self._osquery_binary_path, e
+            ))
+        }) {
+            error!("{}", e.message);
+            warn!(
+                "Running the artifact type '{:?}' using the native tables [{}] duo to the previous error",
+                ArtifactType::Query,
+                NativeTables::names().join(", ")
+            );
+            errors.push(e.message);
+        }
+
+        self._variables = Variables::load(osquery.instance().1.as_ref());
+
+        let this = &*self;
+        let osquery = &osquery;
+        let selected = this.selected_artifacts();
+
+        // The files are listed once before running the artifacts, the listing is reused by the `collection` artifacts
+        let estimated_size = this.list_collections(&selected);
+        if let Some(available) = this._available_space {
+            let needed = match this._max_package_size {
+                Some(max_size) => estimated_size.min(max_size),
+                None => estimated_size,
+            };
+            if available < needed {
+                return Err(FennecError::output_error(format!(
+                    "Not enough free space for the output, '{}' bytes are available and the files to collect are '{}' bytes before compression. Limit the package size or write it to another filesystem",
+                    available, needed
+                )));
+            }
+            info!(
+                "'{}' bytes are available for the output, the files to collect are '{}' bytes before compression",
+                available, needed
+            );
+        }
+        let jobs = this._parallelism.min(selected.len()).max(1);
+        info!(
+            "Processing '{}' of '{}' artifacts using '{}' worker(s)",
+            selected.len(),
+            this._config.artifacts.len(),
+            jobs
+        );
+        let artifacts = Mutex::new(selected.into_iter());
+        let reports = Mutex::new(vec![]);
+
+        thread::scope(|scope| {
+            for worker in 0..jobs {
+                let artifacts = &artifacts;
+                let reports = &reports;
+                let spawned = thread::Builder::new()
+                    .name(format!("worker-{}", worker))
+                    .spawn_scoped(scope, move || loop {
+                        let next = match artifacts.lock() {
+                            Ok(mut iter) => iter.next(),
+                            Err(poisoned) => poisoned.into_inner().next(),
+                        };
+                        match next {
+                            Some(artifact) => {
+                                let report = this.run_artifact(artifact, osquery);
+                                match reports.lock() {
+                                    Ok(mut reports) => reports.push(report),
+                                    Err(poisoned) => poisoned.into_inner().push(report),
+                                }
+                            }
+                            None => break,
+                        }
+                    });
+                if let Err(e) = spawned {
+                    error!("Unable to spawn the worker '{}', ERROR: {}", worker, e);
+                }
+            }
+        });
+
+        let mut reports = match reports.into_inner() {
+            Ok(reports) => reports,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // Keep the order of the configuration, filtered artifacts are reported as skipped
+        let mut artifacts = vec![];
+        for artifact in this._config.artifacts.iter() {
+            match reports
+                .iter()
+                .position(|report| report.name == artifact.name)
+            {
+                Some(index) => artifacts.push(reports.swap_remove(index)),
+                None => {
+                    let mut report =
+                        ArtifactReport::new(&artifact.name, artifact.artifact_type.to_string());
+                    report.skip(String::from(
+                        "not selected by the names, excluded names or tags filters",
+                    ));
+                    artifacts.push(report);
+                }
+            }
+        }
+
+        let mut report = TriageReport {
+            started_at: this._started_at.to_rfc3339(),
+            finished_at: Utc::now().to_rfc3339(),
+            duration_seconds: started.elapsed().as_secs_f64(),
+            success: 0,
+            partial: 0,
+            failed: 0,
+            skipped: 0,
+            osquery_restarts: osquery.restarts(),
+            errors,
+            artifacts,
+        };
+        report.success = report.count(ArtifactStatus::Success);
+        report.partial = report.count(ArtifactStatus::Partial);
+        report.failed = report.count(ArtifactStatus::Failed);
+        report.skipped = report.count(ArtifactStatus::Skipped);
+
+        match serde_json::to_vec_pretty(&report) {
+            Ok(data) => {
+                if let Err(e) =
+                    this.write_entry("triage_report.json", &mut data.as_slice(), None, None)
+                {
+                    error!("Unable to write 'triage_report.json', ERROR: '{}'", e);
+                }
+            }
+            Err(e) => {
+                error!("Unable to serialize the triage report, ERROR: '{}'", e);
+            }
+        }
+        info!(
+            "Artifacts status: '{}' success, '{}' partial, '{}' failed and '{}' skipped",
+            report.success, report.partial, report.failed, report.skipped
+        );
+
+        Ok(report)
+    }
+
+    /// The flags osquery is spawned with, the flags of the configuration then the flags set using `set_osquery_flags`
+    fn osquery_flags(&self) -> Vec<String> {
+        let mut flags = self
+            ._config
+            .osquery
+            .as_ref()
+            .map(|osquery| osquery.flags.clone())
+            .unwrap_or_default();
+        flags.extend(self._osquery_flags.iter().cloned());
+        flags
+    }
+
+    /// The extensions loaded by the spawned osquery, the extensions of the configuration then the extensions set using `set_osquery_extensions`
+    fn osquery_extensions(&self) -> Vec<String> {
+        let mut extensions = self
+            ._config
+            .osquery
+            .as_ref()
+            .map(|osquery| osquery.extensions.clone())
+            .unwrap_or_default();
+        extensions.extend(self._osquery_extensions.iter().cloned());
+        extensions
+    }
+
+    /// Connect to the extension socket of the running osquery daemon if set, the daemon should answer a query to be used
+    fn attach_osquery(&self) -> Option<OSQuery> {
+        let socket = self._osquery_socket.as_ref()?;
+        match Self::ping_osquery(socket, self._osquery_timeout) {
+            Ok(_) => {
+                info!("Using the running osquery daemon at '{}'", socket);
+                if !self.osquery_flags().is_empty() || !self.osquery_extensions().is_empty() {
+                    warn!("The osquery flags and extensions are not used with the running osquery daemon");
+                }
+                Some(
+                    OSQuery::new()
+                        .set_socket(socket)
+                        .set_timeout(self._osquery_timeout),
+                )
+            }
+            Err(e) => {
+                warn!(
+                    "The osquery daemon at '{}' did not answer a query, spawning osquery instead, ERROR: '{}'",
+                    socket, e
+                );
+                None
+            }
+        }
+    }
+
+    /// Check if the osquery daemon listening on the extension socket answers a query, `set_osquery_socket` uses the same check to decide
+    /// if osquery is spawned. Used to decide if the osquery binary should be prepared before running the triage
+    pub fn ping_osquery(socket: &str, timeout: u64) -> Result<(), String> {
+        supervisor::ping(socket, timeout)
+    }
+
+    /// Run a single artifact and write its results to the output file
+    fn run_artifact(&self, artifact: &Artifact, osquery: &OSQuerySupervisor) -> ArtifactReport {
+        let mut report = ArtifactReport::new(&artifact.name, artifact.artifact_type.to_string());
+        if let Some(condition) = &artifact.when {
+            let (_, osquery_instance) = osquery.instance();
+            if let Err(reason) = condition.evaluate(osquery_instance.as_ref()) {
+                info!("Skipping the artifact '{}', {}", artifact.name, reason);
+                report.skip(reason);
+                return report;
+            }
+        }
+
+        let started = Instant::now();
+        match artifact.artifact_type {
+            ArtifactType::Query => self.run_query(artifact, osquery, &mut report),
+            ArtifactType::Collection => self.run_collection(artifact, &mut report),
+            ArtifactType::Command => self.run_command(artifact, &mut report),
+            ArtifactType::Parse => self.run_parse(artifact, &mut report),
+        };
+        report.duration_seconds = started.elapsed().as_secs_f64();
+        report.finish();
+        info!(
+            "Finished processing the artifact '{}' with the status '{:?}', took '{}' seconds",
+            artifact.name, report.status, report.duration_seconds
+        );
+        report
+    }
+
+    /// Run the osquery SQL queries of the artifact, the queries run using the native tables when osquery is not available
+    fn run_query(
+        &self,
+        artifact: &Artifact,
+        osquery: &OSQuerySupervisor,
+        report: &mut ArtifactReport,
+    ) {
+        let mut entry = StagedEntry::new(
+            format!("{}.{}", artifact.name, self._extension),
+            self._staging_limit,
+        );
+        if let Some(timeout) = artifact.timeout {
+            debug!(
+                "Using the timeout '{}' seconds for the osquery SQL queries of the artifact '{}'",
+                timeout, artifact.name
+            );
+        }
+        for sql in artifact.artifacts.iter() {
+            let rows = match self.query_osquery(artifact, sql, osquery, report) {
+                Some(rows) => rows,
+                None => {
+                    report.native = true;
+                    info!(
+                        "Executing the osquery SQL query '{}' for the artifact '{}' using the native tables",
+                        sql, artifact.name
+                    );
+                    self._native_tables
+                        .query(sql, self._typed_results)
+                        .map_err(|e| {
+                            format!(
+                                "Unable to execute osquery SQL query '{}' without osquery, ERROR: {}",
+                                sql, e
+                            )
+                        })
+                }
+            };
+            match rows {
+                Ok(rows) => {
+                    for row in rows {
+                        let mut json = Value::Object(row);
+                        if let Some(data) = artifact.map(&json) {
+                            json = data;
+                        }
+                        self.write_record(&mut entry, artifact, &json, report);
+                    }
+                }
+                Err(e) => report.error(e),
+            }
+        }
+        self.commit_entry(artifact, entry, report);
+    }
+
+    /// Execute the osquery SQL query using osquery, returns `None` if osquery is not running. The query is retried up to `--osquery-retries` times,
+    /// the osquery spawned by Fennec is restarted when it crashes or stops answering and a query that timed out is retried with twice the timeout
+    fn query_osquery(
+        &self,
+        artifact: &Artifact,
+        sql: &str,
+        osquery: &OSQuerySupervisor,
+        report: &mut ArtifactReport,
+    ) -> Option<Result<Vec<serde_json::Map<String, Value>>, String>> {
+        let mut attempt = 0;
+        let mut timeout = artifact.timeout;
+        loop {
+            let (generation, guard) = osquery.instance();
+            let osquery_instance = guard.as_ref()?;
+            info!(
+                "Executing the osquery SQL query '{}' for the artifact '{}'",
+                sql, artifact.name
+            );
+            // The artifact timeout overrides the global timeout, the queries are sent to the same instance using a new client
+            let artifact_instance;
+            let osquery_instance = match timeout {
+                Some(timeout) => {
+                    artifact_instance = OSQuery::new()
+                        .set_socket(&osquery_instance.get_socket())
+                        .set_timeout(timeout);
+                    &artifact_instance
+                }
+                None => osquery_instance,
+            };
+            let started = Instant::now();
+            let error = match osquery_instance.query(sql.to_string()) {
+                Ok(res) => {
+                    let status = res.status.unwrap_or_default();
+                    return Some(match status.code {
+                        Some(0) => {
+                            let data = res.response.unwrap_or_default();
+                            // Without types all the values are strings
+                            let types = match self._typed_results && !data.is_empty() {
+                                true => self._column_types.for_query(osquery_instance, sql),
+                                false => HashMap::new(),
+                            };
+                            Ok(data
+                                .iter()
+                                .map(|row| column_types::convert_row(&types, row))
+                                .collect())
+                        }
+                        _ => Err(format!(
+                            "Unable to execute osquery SQL query '{}', ERROR: '{}'",
+                            sql,
+                            status.message.unwrap_or_default()
+                        )),
+                    });
+                }
+                Err(e) => e,
+            };
+
+            let used_timeout = osquery_instance.get_timeout();
+            let timed_out = started.elapsed() >= Duration::from_secs(used_timeout);
+            // osquery is only restarted when it stops answering, a heavy query that timed out does not mean osquery is stuck
+            let alive = osquery.is_alive(osquery_instance);
+            drop(guard);
+            if !alive {
+                warn!(
+                    "osquery stopped answering the osquery SQL query '{}' of the artifact '{}', ERROR: {}",
+                    sql, artifact.name, error
+                );
+                if !osquery.restart(generation) {
+                    return None;
+                }
+            }
+            if attempt >= self._osquery_retries {
+                return Some(Err(format!(
+                    "Unable to execute osquery SQL query '{}' after '{}' retries, ERROR: {}",
+                    sql, attempt, error
+                )));
+            }
+            attempt += 1;
+            report.osquery_retries += 1;
+            if timed_out && alive {
+                timeout = Some(used_timeout * 2);
+            }
+            warn!(
+                "Retrying the osquery SQL query '{}' of the artifact '{}' with the timeout '{}' seconds, retry '{}' of '{}'",
+                sql,
+                artifact.name,
+                timeout.unwrap_or(used_timeout),
+                attempt,
+                self._osquery_retries
+            );
+        }
+    }
+
+    /// Expand the variables in the paths of the artifact, the paths that can not be expanded are reported as errors
+    fn expand_paths(&self, artifact: &Artifact, report: &mut ArtifactReport) -> Vec<Expanded> {
+        let mut patterns = vec![];
+        for path in artifact.artifacts.iter() {
+            match self._variables.expand(path) {
+                Ok(mut expanded) => patterns.append(&mut expanded),
+                Err(e) => report.error(format!(
+                    "Unable to expand the variables in the path '{}' for the artifact '{}', ERROR: {}",
+                    path, artifact.name, e
+                )),
+            }
+        }
+        patterns
+    }
+
+    /// List the paths of the selected `collection` artifacts that are not skipped by the conditions evaluated without osquery,
+    /// returns the total size of the listed files
+    fn list_collections(&self, selected: &[&Artifact]) -> u64 {
+        let mut size = 0;
+        for artifact in selected.iter().filter(|artifact| {
+            matches!(artifact.artifact_type, ArtifactType::Collection)
+                && artifact
+                    .when
+                    .as_ref()
+                    .is_none_or(|condition| condition.evaluate_local().is_ok())
+        }) {
+            let listing = self.list_collection(artifact);
+            size += listing
+                .paths
+                .iter()
+                .filter_map(|(path, _)| std::fs::metadata(path).ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.size())
+                .sum::<u64>();
+            match self._collections.lock() {
+                Ok(mut collections) => collections.insert(artifact.name.clone(), listing),
+                Err(poisoned) => poisoned.into_inner().insert(artifact.name.clone(), listing),
+            };
+        }
+        size
+    }
+
+    /// Expand the variables & the glob patterns of the `collection` artifact to the paths it collects
+    fn list_collection(&self, artifact: &Artifact) -> CollectionListing {
+        let mut report = ArtifactReport::new(&artifact.name, artifact.artifact_type.to_string());
+        let mut paths = vec![];
+        for Expanded { pattern, user } in self.expand_paths(artifact, &mut report) {
+            let entries = match glob::glob(&pattern) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    report.error(format!(
+                        "Invalid glob pattern '{}' for the artifact '{}', ERROR: '{}'",
+                        pattern, artifact.name, e
+                    ));
+                    continue;
+                }
+            };
+            for entry in entries {
+                match entry {
+                    Ok(entry) => paths.push((entry, user.clone())),
+                    Err(e) => report.error(format!(
+                        "Error in glob for the artifact '{}', ERROR: '{:?}'",
+                        artifact.name, e
+                    )),
+                }
+            }
+        }
+        CollectionListing {
+            paths,
+            errors: report.errors,
+        }
+    }
+
+    fn run_collection(&self, artifact: &Artifact, report: &mut ArtifactReport) {
+        let mut metadata_entry = StagedEntry::new(
+            format!(
+                "{}/{}_metadata.{}",
+                artifact.name, artifact.name, self._extension
+            ),
+            self._staging_limit,
+        );
+        let mut headers: Vec<String> = [
+            "full_path",
+            "file_type",
+            "size",
+            "permessions",
+            "owner_uid",
+            "owner_gid",
+            "mtime",
+            "atime",
+            "ctime",
+            "skipped",
+        ]
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+        if artifact.uses_user_variables() {
+            headers.push(String::from("user"));
+        }
+        let hashes = artifact.hashes();
+        headers.extend(hashes.iter().map(|hash| hash.to_string()));
+        metadata_entry.csv_headers = Some(headers);
+        if let OutputFormat::CSV = self._extension {
+            // Metadata headers are written even if no files were collected
+            self.write_csv_headers(&mut metadata_entry, artifact, report);
+        }
+
+        let listing = match self._collections.lock() {
+            Ok(mut collections) => collections.remove(&artifact.name),
+            Err(poisoned) => poisoned.into_inner().remove(&artifact.name),
+        }
+        .unwrap_or_else(|| self.list_collection(artifact));
+        // The errors were logged when the paths were listed
+        report.errors.extend(listing.errors);
+        for (entry, user) in listing.paths {
+            let dest_path: PathBuf = entry.as_path().components().skip(1).collect();
+
+            let dest_path: PathBuf = [
+                artifact.name.clone(),
+                dest_path.as_path().to_string_lossy().to_string(),
+            ]
+            .iter()
+            .collect();
+
+            // Collect metadta for file/folder, the record is written after the file is copied to add its hashes
+            let mut reserved = 0;
+            let mut record = None;
+            let mut skipped = false;
+            match std::fs::metadata(entry.as_path()) {
+                Ok(metadata) => {
+                    if metadata.is_file() {
+                        reserved = metadata.size();
+                    }
+                    // The smaller files after it can still fit, the skipped files are recorded in the metadata
+                    if !self.reserve(reserved) {
+                        skipped = true;
+                        match report.truncated {
+                            false => report.truncate(format!(
+                                "The package size budget of '{}' bytes was reached, the files of the artifact '{}' that do not fit are skipped starting at '{}' and have the field 'skipped' set in the metadata",
+                                self._max_package_size.unwrap_or_default(),
+                                artifact.name,
+                                entry.as_path().to_string_lossy()
+                            )),
+                            true => info!(
+                                "Skipped the file '{}' for the artifact '{}', it does not fit in the package size budget",
+                                entry.as_path().to_string_lossy(),
+                                artifact.name
+                            ),
+                        }
+                    }
+                    let mtime: DateTime<Utc> = metadata
+                        .modified()
+                        .unwrap_or_else(|_| SystemTime::now())
+                        .into();
+                    let atime: DateTime<Utc> = metadata
+                        .accessed()
+                        .unwrap_or_else(|_| SystemTime::now())
+                        .into();
+                    let ctime: DateTime<Utc> = metadata
+                        .created()
+                        .unwrap_or_else(|_| SystemTime::now())
+                        .into();
+                    let per = metadata.permissions().mode() & 0x1ff;
+                    let file_type = match metadata.is_file() {
+                        true => "file",
+                        false => match metadata.is_dir() {
+                            true => "directory",
+                            false => "other",
+                        },
+                    };
+                    let mut data = json!({
+                        "full_path": entry.as_path().to_string_lossy(),
+                        "type": file_type,
+                        "size": metadata.size(),
+                        "permessions": format!("{:03o}", per),
+                        "owner_uid": metadata.uid(),
+                        "owner_gid": metadata.gid(),
+                        "mtime": mtime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        "atime": atime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        "ctime": ctime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        "skipped": skipped,
+                    });
+                    if artifact.uses_user_variables() {
+                        data["user"] = json!(user);
+                    }
+                    record = Some(data);
+                }
+                Err(e) => {
+                    report.error(format!(
+                        "Unable to collect metadata for '{}' for the artifact '{}', ERROR: '{}'",
+                        entry.as_path().to_string_lossy(),
+                        artifact.name,
+                        e
+                    ));
+                }
+            }
+
+            let mut file_hashes = None;
+            if skipped {
+                // Only the metadata record is written
+            } else if entry.as_path().is_dir() {
+                match self
+                    .output()
+                    .add_directory(&dest_path.as_path().to_string_lossy())
+                {
+                    Ok(_) => {
+                        debug!("Created directory entry for '{}' in ZIP file for artifact '{}' successfully!", dest_path.as_path().to_string_lossy(), artifact.name);
+                        self.record(ManifestEntry {
+                            name: dest_path.as_path().to_string_lossy().to_string(),
+                            size: 0,
+                            sha256: None,
+                            source_path: Some(entry.as_path().to_string_lossy().to_string()),
+                            artifact: Some(artifact.name.clone()),
+                        });
+                    }
+                    Err(e) => {
+                        report.error(format!("Unable to create directory entry '{}' in ZIP file for the artifact '{}', ERROR: '{}'",dest_path.as_path().to_string_lossy(), artifact.name, e));
+                    }
+                }
+            } else {
+                file_hashes = self.copy_file(
+                    artifact,
+                    entry.as_path(),
+                    dest_path.as_path(),
+                    reserved,
+                    &hashes,
+                    report,
+                );
+            }
+
+            if let Some(mut data) = record {
+                // Directories and the files that were not copied or skipped do not have hashes
+                for hash in hashes.iter() {
+                    data[hash.to_string()] =
+                        json!(file_hashes.as_ref().and_then(|file_hashes| match hash {
+                            HashAlgorithm::Md5 => file_hashes.md5.clone(),
+                            HashAlgorithm::Sha1 => file_hashes.sha1.clone(),
+                            HashAlgorithm::Sha256 => Some(file_hashes.sha256.clone()),
+                        }));
+                }
+                self.write_record(&mut metadata_entry, artifact, &data, report);
+            }
+        }
+        self.commit_entry(artifact, metadata_entry, report);
+    }
+
+    /// Write the CSV headers of the staged entry, used when the headers are needed before any record is written
+    fn write_csv_headers(
+        &self,
+        entry: &mut StagedEntry,
+        artifact: &Artifact,
+        report: &mut ArtifactReport,
+    ) {
+        if let Some(headers) = &entry.csv_headers {
+            let mut writer = Writer::from_writer(vec![]);
+            writer.write_record(headers).unwrap();
+            let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+            match entry.data.write_all(data.as_bytes()) {
+                Ok(_) => {
+                    debug!(
+                        "Wrote headers for the artifact '{}' to '{}'",
+                        artifact.name, entry.name
+                    );
+                }
+                Err(e) => {
+                    report.error(format!(
+                        "Unable to write the results for the artifact '{}' to '{}', ERROR: '{}'",
+                        artifact.name, entry.name, e
+                    ));
+                }
+            }
+            entry.csv_headers_printed = true;
+        }
+    }
+
+    /// Copy a single file to the output file and return its hashes, `reserved` is the size reserved for the file from the package size budget
+    fn copy_file(
+        &self,
+        artifact: &Artifact,
+        src_path: &Path,
+        dest_path: &Path,
+        reserved: u64,
+        hashes: &[HashAlgorithm],
+        report: &mut ArtifactReport,
+    ) -> Option<Hashes> {
+        let in_file = match File::open(src_path) {
+            Ok(in_file) => in_file,
+            Err(e) => {
+                self._package_size.fetch_sub(reserved, Ordering::SeqCst);
+                report.error(format!(
+                    "Unable to open the file '{}' for the artifact '{}', ERROR: '{}'",
+                    src_path.to_string_lossy(),
+                    artifact.name,
+                    e
+                ));
+                return None;
+            }
+        };
+
+        info!(
+            "Copying the file '{}' for the artifact '{}'",
+            src_path.to_string_lossy(),
+            artifact.name
+        );
+
+        // The size is passed to the output so the tar output streams the large files instead of staging them in a temporary file.
+        // The tar output stages the smaller files, the size of pseudo files such as the files in `/proc` & `/sys` does not match their content
+        let size = in_file
+            .metadata()
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len());
+        let mut reader =
+            BufReader::with_capacity(self._file_collect_buf_size, self.throttled_reader(in_file));
+        match self.write_hashed_entry(
+            &dest_path.to_string_lossy(),
+            &mut reader,
+            size,
+            Some(src_path),
+            Some(artifact),
+            hashes,
+        ) {
+            Ok((bytes, file_hashes, changed)) => {
+                if let Some(changed) = changed {
+                    report.error(format!(
+                        "The file '{}' for the artifact '{}' changed while it was copied, {}",
+                        src_path.to_string_lossy(),
+                        artifact.name,
+                        changed
+                    ));
+                }
+                debug!(
+                    "Finished writing '{}' bytes of the file '{}' for the artifact '{}' to the ZIP file",
+                    bytes,
+                    dest_path.to_string_lossy(),
+                    artifact.name
+                );
+                report.files += 1;
+                report.bytes_written += bytes;
+                // The file size can change after it was reserved
+                match bytes > reserved {
+                    true => self
+                        ._package_size
+                        .fetch_add(bytes - reserved, Ordering::SeqCst),
+                    false => self
+                        ._package_size
+                        .fetch_sub(reserved - bytes, Ordering::SeqCst),
+                };
+                Some(file_hashes)
+            }
+            Err(e) => {
+                report.error(format!(
+                    "Unable to write the file '{}' for the artifact '{}' to '{}', ERROR: '{}'",
+                    src_path.to_string_lossy(),
+                    artifact.name,
+                    dest_path.to_string_lossy(),
+                    e
+                ));
+                None
+            }
+        }
+    }
+
+    fn run_command(&self, artifact: &Artifact, report: &mut ArtifactReport) {
+        let mut entry = StagedEntry::new(
+            format!("{}.{}", artifact.name, self._extension),
+            self._staging_limit,
+        );
+        if artifact.regex.is_none() {
+            entry.csv_headers = Some(vec!["line".to_string(), "stdout/stderr".to_string()]);
+        }
+
+        let shell = match std::env::var_os("SHELL") {
+            Some(env) => env.to_string_lossy().to_string(),
+            None => {
+                if Path::new("/bin/bash").exists() {
+                    String::from("/bin/bash")
+                } else if Path::new("/bin/sh").exists() {
+                    String::from("/bin/sh")
+                } else {
+                    report.error(String::from("Unable to find shell ($SHELL, /bin/bash and /bin/sh not found), Skipping command execution"));
+                    self.commit_entry(artifact, entry, report);
+                    return;
+                }
+            }
+        };
+
+        let regex = match &artifact.regex {
+            Some(regex) => match Regex::new(regex) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    report.error(format!(
+                        "Unable to parse the regulare expretion for the artifact '{}', ERROR: {}",
+                        artifact.name, e
+                    ));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        for command in artifact.artifacts.iter() {
+            let mut child = match Command::new(&shell)
+                .arg("-c")
+                .arg(command)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Ok(child) => child,
+                Err(e) => {
+                    report.error(format!(
+                        "Unable to execute the command '{}' for the artifact '{}', ERROR: {}",
+                        command, artifact.name, e
+                    ));
+                    continue;
+                }
+            };
+            let pid = child.id();
+            let timeout_in = match artifact.timeout {
+                Some(timeout) => {
+                    info!("Started the command '{}' for the artifact '{}' with timeout of '{}' seconds. PID: {}", command, artifact.name, timeout, pid);
+                    Duration::from_secs(timeout)
+                }
+                None => {
+                    info!("Started the command '{}' for the artifact '{}' without timeout. Setting timout to '600' seconds. Make sure to set timeout for all command artifacts to aviod command taking unresonable time to finish. PID: {}", command, artifact.name, pid);
+                    Duration::from_secs(600)
+                }
+            };
+            let started_execution = Instant::now();
+
+            let mut counter = 0;
+
+            // Keep reading stdout until the process exits or the process is killed due to timeout
+            loop {
+                match child.stdout.as_mut() {
+                    Some(stdout) => match stdout.read_until(0xA) {
+                        Ok(line) => {
+                            if !line.is_empty() {
+                                match (&artifact.regex, &regex) {
+                                    (Some(_), Some(re)) => {
+                                        if let Some(groups) = re.captures(&line) {
+                                            let mut data: serde_json::Map<String, Value> =
+                                                serde_json::Map::new();
+                                            re.capture_names().for_each(|name| {
+                                                if let Some(name) = name {
+                                                    let value = match groups.name(name) {
+                                                        Some(m) => {
+                                                            Value::String(m.as_str().to_string())
+                                                        }
+                                                        None => Value::Null,
+                                                    };
+                                                    data.insert(name.to_string(), value);
+                                                }
+                                            });
+
+                                            let mut json = Value::Object(data);
+
+                                            if let Some(data) = artifact.map(&json) {
+                                                json = data;
+                                            }
+                                            self.write_record(&mut entry, artifact, &json, report);
+                                        } else {
+                                            error!(
+                                                "Unable to parse the line '{}' for the artifact '{}'",
+                                                line, artifact.name
+                                            );
+                                        }
+                                    }
+                                    (Some(_), None) => {}
+                                    (None, _) => {
+                                        let mut row = json!({
+                                            "line": counter,
+                                            "stdout": line
+                                        });
+
+                                        if let Some(data) = artifact.map(&row) {
+                                            row = data;
+                                        }
+                                        self.write_record(&mut entry, artifact, &row, report);
+                                    }
+                                }
+                                counter += 1;
+                            }
+                        }
+                        Err(_) => match child.try_wait() {
+                            Ok(Some(status)) => {
+                                let status_code = match status.code() {
+                                    Some(code) => format!("{code}"),
+                                    None => String::from("UNKNOWN"),
+                                };
+                                info!(
+                                    "Process for the command '{}' and PID '{}' exited with the status: {}, finished writing the results",
+                                    command,
+                                    pid,
+                                    status_code
+                                );
+                                break;
+                            }
+                            Ok(None) => {
+                                debug!("Process with the PID '{pid}' still running, keep reading stdout");
+                            }
+                            Err(e) => {
+                                error!("Error retriving process status for the process with the PID: {pid}, ERROR: {e}");
+                            }
+                        },
+                    },
+                    None => {
+                        error!(
+                            "Unable to retrive stdout stream for the command '{}' for the artifact '{}'",
+                            command, artifact.name
+                        );
+                    }
+                }
+
+                if started_execution.elapsed() >= timeout_in {
+                    match child.kill() {
+                        Ok(_) => match child.wait() {
+                            Ok(status) => {
+                                let code = match status.code() {
+                                    Some(scode) => format!("{scode}"),
+                                    None => String::from("UNKNOWN"),
+                                };
+                                info!("Process for the command '{}' (PID: {}) for the artifact '{}' killed due to timeout, Status: {}",command, pid, artifact.name, code);
+                                report.errors.push(format!("The command '{}' was killed after reaching the timeout of '{}' seconds", command, timeout_in.as_secs()));
+                            }
+                            Err(e) => {
+                                warn!("Unable to kill the process for the command '{}' (PID: {}) for the artifact '{}', ERROR: {}",command, pid, artifact.name, e);
+                            }
+                        },
+                        Err(e) => {
+                            warn!("Unable to kill the process for the command '{}' (PID: {}) for the artifact '{}', ERROR: {}",command, pid, artifact.name, e);
+                        }
+                    }
+                    break;
+                }
+            }
+
+            counter = 0;
+
+            // Keep reading stderr until the process exits or the process is killed due to timeout
+            loop {
+                match child.stderr.as_mut() {
+                    Some(stderr) => match stderr.read_until(0xA) {
+                        Ok(line) => {
+                            if !line.is_empty() {
+                                let mut row = json!({
+                                    "line": counter,
+                                    "stderr": line
+                                });
+
+                                if let Some(data) = artifact.map(&row) {
+                                    row = data;
+                                }
+
+                                self.write_record(&mut entry, artifact, &row, report);
+                                counter += 1;
+                            }
+                        }
+                        Err(_) => {
+                            debug!("Done reading the stderr for the command '{}' (PID: {}) for the artifact '{}'", command, pid, artifact.name);
+                            break;
+                        }
+                    },
+                    None => {
+                        error!(
+                            "Unable to retrive stderr stream for the command '{}' for the artifact '{}'",
+                            command, artifact.name
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        self.commit_entry(artifact, entry, report);
+    }
+
+    fn run_parse(&self, artifact: &Artifact, report: &mut ArtifactReport) {
+        let mut entry = StagedEntry::new(
+            format!("{}.{}", artifact.name, self._extension),
+            self._staging_limit,
+        );
+
+        let re = match &artifact.regex {
+            Some(regex) => match Regex::new(regex.as_str()) {
+                Ok(re) => re,
+                Err(e) => {
+                    report.error(format!(
+                        "Unable to parse the regulare expretion for the artifact '{}', ERROR: {}",
+                        artifact.name, e
+                    ));
+                    self.commit_entry(artifact, entry, report);
+                    return;
+                }
+            },
+            None => {
+                report.error(format!(
+                    "The artifact '{}' has the artifact type 'parse' which regires the field 'regex'",
+                    artifact.name
+                ));
+                self.commit_entry(artifact, entry, report);
+                return;
+            }
+        };
+
+        for Expanded { pattern, user } in self.expand_paths(artifact, report) {
+            let paths = match glob::glob(&pattern) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    report.error(format!(
+                        "Invalid glob pattern '{}' for the artifact '{}', ERROR: '{}'",
+                        pattern, artifact.name, e
+                    ));
+                    continue;
+                }
+            };
+            for file_entry in paths {
+                match file_entry {
+                    Ok(file_entry) => {
+                        if !file_entry.as_path().is_dir() {
+                            self.parse_file(
+                                artifact,
+                                &re,
+                                file_entry.as_path(),
+                                user.as_deref(),
+                                &mut entry,
+                                report,
+                            );
+                        }
+                    }
+                    Err(e) => report.error(format!(
+                        "Error in glob for the artifact '{}', ERROR: '{:?}'",
+                        artifact.name, e
+                    )),
+                }
+            }
+        }
+        self.commit_entry(artifact, entry, report);
+    }
+
+    /// Parse a single file line by line using the artifact regex, gzip compressed files are supported. `user` is the user the file belongs to
+    /// if the path was expanded for every user
+    fn parse_file(
+        &self,
+        artifact: &Artifact,
+        re: &Regex,
+        path: &Path,
+        user: Option<&str>,
+        entry: &mut StagedEntry,
+        report: &mut ArtifactReport,
+    ) {
+        info!(
+            "Parsing the file '{}' for the artifact '{}'",
+            path.to_string_lossy(),
+            artifact.name
+        );
+        let in_file = match File::open(path) {
+            Ok(in_file) => in_file,
+            Err(e) => {
+                report.error(format!(
+                    "Unable to open the file '{}' for the artifact '{}', ERROR: '{}'",
+                    path.to_string_lossy(),
+                    artifact.name,
+                    e
+                ));
+                return;
+            }
+        };
+        let in_file = self.throttled_reader(in_file);
+        let reader = match path.to_string_lossy().ends_with(".gz") {
+            true => Box::new(BufReader::new(GzDecoder::new(in_file))) as Box<dyn BufRead>,
+            false => Box::new(BufReader::new(in_file)) as Box<dyn BufRead>,
+        };
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!(
+                        "A line in the file '{}' is not a string, ERROR: {}",
+                        path.to_string_lossy(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if let Some(groups) = re.captures(&line) {
+                let mut data: serde_json::Map<String, Value> = serde_json::Map::new();
+
+                re.capture_names().for_each(|name| {
+                    if let Some(name) = name {
+                        let value = match groups.name(name) {
+                            Some(m) => Value::String(m.as_str().to_string()),
+                            None => Value::Null,
+                        };
+                        data.insert(name.to_string(), value);
+                    }
+                });
+
+                data.insert(
+                    "full_path".to_string(),
+                    Value::String(path.to_string_lossy().to_string()),
+                );
+                // A regex group with the same name takes precedence
+                if artifact.uses_user_variables() && !data.contains_key("user") {
+                    data.insert("user".to_string(), json!(user));
+                }
+
+                let mut json = Value::Object(data);
+
+                if let Some(data) = artifact.map(&json) {
+                    json = data;
+                }
+
+                self.write_record(entry, artifact, &json, report);
+            } else {
+                error!(
+                    "Unable to parse the line '{}' for the artifact '{}'",
+                    line, artifact.name
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps the entries of the package in memory
+    #[derive(Default)]
+    struct MemorySink {
+        entries: Vec<(String, Vec<u8>)>,
+    }
+
+    impl Write for MemorySink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            match self.entries.last_mut() {
+                Some((_, data)) => data.write(buf),
+                None => Err(std::io::Error::other("no entry started")),
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl OutputSink for MemorySink {
+        fn start_entry(&mut self, name: &str) -> std::io::Result<()> {
+            self.entries.push((name.to_string(), vec![]));
+            Ok(())
+        }
+
+        fn add_directory(&mut self, name: &str) -> std::io::Result<()> {
+            self.start_entry(name)
+        }
+
+        fn finish(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn triage_runs_artifacts_on_the_worker_pool() {
+        let mut config = String::from("artifacts:\n");
+        for i in 0..8 {
+            config.push_str(&format!(
+                "  - name: command_{}\n    type: command\n    commands:\n      - 'for n in 1 2 3 4 5; do echo {}-$n; sleep 0.01; done'\n",
+                i, i
+            ));
+        }
+        config.push_str(
+            "  - name: skipped\n    type: command\n    commands:\n      - 'echo skipped'\n",
+        );
+
+        let mut sink = MemorySink::default();
+        let mut fennec = Fennec::from_reader(config.as_bytes(), &mut sink)
+            .unwrap()
+            .set_osquery_binary_path("/nonexistent/osqueryd")
+            .set_excluded_artifacts(vec![String::from("skipped")])
+            .set_parallelism(4);
+        // A worker that panicked while writing to the output does not stop the other workers
+        let _ = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _output = fennec.output();
+                    panic!("poison the output lock");
+                })
+                .join()
+        });
+        assert!(fennec._output.is_poisoned());
+
+        let report = fennec.triage().unwrap();
+        drop(fennec);
+
+        // The reports keep the order of the configuration whatever the order the workers finished in
+        let names: Vec<&str> = report.artifacts.iter().map(|a| a.name.as_str()).collect();
+        let mut expected: Vec<String> = (0..8).map(|i| format!("command_{}", i)).collect();
+        expected.push(String::from("skipped"));
+        assert_eq!(names, expected);
+        assert_eq!(report.success, 8);
+        assert_eq!(report.skipped, 1);
+
+        // Every artifact is committed as a single entry, the entries of the workers are not interleaved
+        for i in 0..8 {
+            let name = format!("command_{}.jsonl", i);
+            let entries: Vec<&Vec<u8>> = sink
+                .entries
+                .iter()
+                .filter(|(entry, _)| *entry == name)
+                .map(|(_, data)| data)
+                .collect();
+            assert_eq!(entries.len(), 1, "{}", name);
+            let lines: Vec<String> = String::from_utf8_lossy(entries[0])
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(line).unwrap()["stdout"].to_string())
+                .collect();
+            assert_eq!(lines.len(), 5, "{}", name);
+            assert!(lines
+                .iter()
+                .all(|line| line.starts_with(&format!("\"{}-", i))));
+        }
+        assert!(sink
+            .entries
+            .iter()
+            .any(|(name, _)| name == "triage_report.json"));
+    }
+
+    #[test]
+    fn streamed_entries_keep_their_size() {
+        let mut sink = sink::TarSink::new(vec![]).set_staging_limit(4);
+        let fennec = Fennec::from_reader("artifacts: []".as_bytes(), &mut sink).unwrap();
+        let (size, _, changed) = fennec
+            .write_hashed_entry("grew", &mut &b"0123456789"[..], Some(8), None, None, &[])
+            .unwrap();
+        assert_eq!(size, 8);
+        assert!(changed.unwrap().contains("is truncated"));
+        let (size, _, changed) = fennec
+            .write_hashed_entry("shrank", &mut &b"0123456"[..], Some(8), None, None, &[])
+            .unwrap();
+        assert_eq!(size, 8);
+        assert!(changed.unwrap().contains("padded with zeros"));
+        // The small entries are staged by the tar output and keep all their data
+        let (size, _, changed) = fennec
+            .write_hashed_entry("small", &mut &b"0123456"[..], Some(2), None, None, &[])
+            .unwrap();
+        assert_eq!((size, changed), (7, None));
+    }
+
+    #[test]
+    fn entries_are_not_cut_when_the_output_does_not_stream() {
+        let mut sink = MemorySink::default();
+        let fennec = Fennec::from_reader("artifacts: []".as_bytes(), &mut sink).unwrap();
+        let (size, _, changed) = fennec
+            .write_hashed_entry("grew", &mut &b"0123456789"[..], Some(8), None, None, &[])
+            .unwrap();
+        assert_eq!((size, changed), (10, None));
+        drop(fennec);
+        assert_eq!(
+            sink.entries[0],
+            (String::from("grew"), b"0123456789".to_vec())
+        );
+    }
+
+    #[test]
+    fn skipped_files_are_recorded_in_the_metadata() {
+        let root = std::env::temp_dir().join(format!("fennec-budget-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a_big.bin"), vec![0; 4096]).unwrap();
+        std::fs::write(root.join("b_small.txt"), b"small").unwrap();
+        let config = format!(
+            "artifacts:\n  - name: files\n    type: collection\n    paths:\n      - '{}/*'\n",
+            root.to_string_lossy()
+        );
+
+        let mut sink = MemorySink::default();
+        let mut fennec = Fennec::from_reader(config.as_bytes(), &mut sink)
+            .unwrap()
+            .set_osquery_binary_path("/nonexistent/osqueryd")
+            .set_max_package_size(1024);
+        let report = fennec.triage().unwrap();
+        drop(fennec);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(report.artifacts[0].truncated);
+        assert_eq!(report.artifacts[0].files, 1);
+        let (_, metadata) = sink
+            .entries
+            .iter()
+            .find(|(name, _)| name == "files/files_metadata.jsonl")
+            .unwrap();
+        let records: Vec<Value> = String::from_utf8_lossy(metadata)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["skipped"], json!(true));
+        assert_eq!(records[0]["sha256"], Value::Null);
+        assert_eq!(records[1]["skipped"], json!(false));
+        assert!(records[1]["sha256"].is_string());
     }
 }
@@ -3,10 +3,12 @@ use std::{fmt::Display, time::SystemTime};
 use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
 
 use log::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Name of the modifier
 pub enum ModifierType {
     #[serde(rename = "epoch_to_iso")]
     EpochToISO,
@@ -29,57 +31,59 @@ impl Display for ModifierType {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Run post processing function on the value of the field
 pub struct Modifier {
+    /// Name of the modifier to use
     name: ModifierType,
-    parameters: Option<Value>,
+    /// Parameters passed to the modifier
+    #[serde(default)]
+    parameters: ModifierParameters,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+/// Parameters of the modifiers, check the modifiers table in the README for the parameters used by every modifier
+pub struct ModifierParameters {
+    /// The date and time format of the input (reference: https://docs.rs/chrono/latest/chrono/format/strftime/index.html), required by `datetime_to_iso` & `time_without_year_to_iso`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_time_format: Option<String>,
+    /// The date and time format of the output (reference: https://docs.rs/chrono/latest/chrono/format/strftime/index.html), default is `%Y-%m-%d %H:%M:%S`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_time_format: Option<String>,
+    /// Process the input date and time without timezone info in the local timezone of the system instead of UTC, default is `false`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_timezone: Option<bool>,
 }
 
 impl Modifier {
-    pub fn get_param(&self, name: &str) -> Option<String> {
-        match &self.parameters {
-            Some(params) => match params {
-                Value::Object(obj) => match obj.get(name) {
-                    Some(param_value) => match param_value {
-                        Value::String(value) => Some(value.clone()),
-                        Value::Number(value) => Some(value.to_string()),
-                        _ => Some(param_value.to_string()),
-                    },
-                    None => None,
-                },
-                _ => {
-                    error!(
-                        "The parameters for the modifier '{}' should be and object",
+    /// Checks the parameters of the modifier, returns the problem if the parameters are not valid
+    pub fn check(&self) -> Result<(), String> {
+        match self.name {
+            ModifierType::DatetimeTimeToISO | ModifierType::TimeWithoutYearToISO => {
+                match self.parameters.input_time_format {
+                    Some(_) => Ok(()),
+                    None => Err(format!(
+                        "The parameter 'input_time_format' for the modifier '{}' is required",
                         self.name
-                    );
-                    None
+                    )),
                 }
-            },
-            None => None,
+            }
+            ModifierType::EpochToISO | ModifierType::ToInt => Ok(()),
         }
     }
+
     pub fn run(&self, field: Value) -> Value {
-        let time_format = match self.get_param("output_time_format") {
-            Some(format) => format,
+        let time_format = match &self.parameters.output_time_format {
+            Some(format) => format.clone(),
             None => "%Y-%m-%d %H:%M:%S".to_string(),
         };
-        let local_timezone = match self.get_param("local_timezone") {
-            Some(value) => {
-                if value.to_lowercase() == "true" {
-                    true
-                } else {
-                    false
-                }
-            }
-            None => false,
-        };
+        let local_timezone = self.parameters.local_timezone.unwrap_or(false);
         match self.name {
             ModifierType::EpochToISO => match field {
                 Value::String(ref epoch_str) => {
-                    let secs: i64;
-                    if epoch_str.contains(".") {
-                        let parts = epoch_str.split(".").collect::<Vec<&str>>();
-                        secs = match parts[0].parse() {
+                    let secs: i64 = if epoch_str.contains('.') {
+                        let parts = epoch_str.split('.').collect::<Vec<&str>>();
+                        match parts[0].parse() {
                             Ok(s) => s,
                             Err(e) => {
                                 error!(
@@ -88,9 +92,9 @@ impl Modifier {
                                 );
                                 0
                             }
-                        };
+                        }
                     } else {
-                        secs = match epoch_str.parse() {
+                        match epoch_str.parse() {
                             Ok(s) => s,
                             Err(e) => {
                                 error!(
@@ -99,8 +103,8 @@ impl Modifier {
                                 );
                                 0
                             }
-                        };
-                    }
+                        }
+                    };
                     match NaiveDateTime::from_timestamp_opt(secs, 0) {
                         Some(iso_time) => Value::String(iso_time.format(&time_format).to_string()),
                         None => {
@@ -133,8 +137,8 @@ impl Modifier {
             ModifierType::DatetimeTimeToISO => match &field {
                 Value::String(datetime_str) => {
                     let datetime_str = datetime_str.clone();
-                    let input_time_format = match self.get_param("input_time_format") {
-                        Some(format) => format,
+                    let input_time_format = match &self.parameters.input_time_format {
+                        Some(format) => format.clone(),
                         None => {
                             error!("The parameter 'input_time_format' for the modifier 'datetime_to_iso' is required");
                             String::from("")
@@ -142,7 +146,7 @@ impl Modifier {
                     };
 
                     let tz_offset = match local_timezone {
-                        true => Local.timestamp_opt(0, 0).unwrap().offset().clone(),
+                        true => *Local.timestamp_opt(0, 0).unwrap().offset(),
                         false => FixedOffset::east_opt(0).unwrap(),
                     };
 
@@ -190,15 +194,15 @@ impl Modifier {
                     // Add current year then check if parser time < current time, if it is then it is the correct time
                     // otherwise it is the previous year
                     let datetime_str = datetime_str.clone();
-                    let input_time_format = match self.get_param("input_time_format") {
-                        Some(format) => format,
+                    let input_time_format = match &self.parameters.input_time_format {
+                        Some(format) => format.clone(),
                         None => {
                             error!("The parameter 'input_time_format' for the modifier 'time_without_year_to_iso' is required");
                             String::from("")
                         }
                     };
                     let tz_offset = match local_timezone {
-                        true => Local.timestamp_opt(0, 0).unwrap().offset().clone(),
+                        true => *Local.timestamp_opt(0, 0).unwrap().offset(),
                         false => FixedOffset::east_opt(0).unwrap(),
                     };
                     let current_time: DateTime<Utc> = SystemTime::now().into();
@@ -0,0 +1,245 @@
+use crate::{errors::FennecError, Artifact, FennecConfig, ARTIFACTS_ALIASES};
+
+enum Operation {
+    /// `key=value`, replace the value of the field, an empty value unsets optional fields
+    Set,
+    /// `key+=value`, append the value to a list field
+    Append,
+}
+
+/// A configuration override from the command line in the format `artifact.<name>.<field>=<value>` or `artifact.<name>.<field>+=<value>`,
+/// the name supports glob to change multiple artifacts (ex. `artifact.*.timeout=30`)
+struct Override<'a> {
+    raw: &'a str,
+    name: &'a str,
+    field: &'a str,
+    operation: Operation,
+    value: &'a str,
+}
+
+impl<'a> Override<'a> {
+    fn parse(raw: &'a str) -> Result<Self, FennecError> {
+        let invalid = |reason: &str| {
+            FennecError::config_error(format!(
+                "Invalid configuration override '{}', {}. The format is 'artifact.<name>.<field>=<value>' or 'artifact.<name>.<field>+=<value>'",
+                raw, reason
+            ))
+        };
+
+        let (key, value) = raw
+            .split_once('=')
+            .ok_or_else(|| invalid("the value is missing"))?;
+        let (key, operation) = match key.strip_suffix('+') {
+            Some(key) => (key, Operation::Append),
+            None => (key, Operation::Set),
+        };
+        let key = key
+            .strip_prefix("artifact.")
+            .ok_or_else(|| invalid("only the artifacts fields can be overridden"))?;
+        // The field names do not contain '.', the artifact name may
+        let (name, field) = key
+            .rsplit_once('.')
+            .ok_or_else(|| invalid("the field name is missing"))?;
+
+        Ok(Self {
+            raw,
+            name,
+            field,
+            operation,
+            value,
+        })
+    }
+
+    fn error(&self, reason: String) -> FennecError {
+        FennecError::config_error(format!(
+            "Unable to apply the configuration override '{}', {}",
+            self.raw, reason
+        ))
+    }
+
+    /// Returns the value of an optional field, an empty value unsets the field
+    fn optional(&self) -> Option<String> {
+        match self.value.is_empty() {
+            true => None,
+            false => Some(self.value.to_string()),
+        }
+    }
+
+    fn apply(&self, artifact: &mut Artifact) -> Result<(), FennecError> {
+        match (self.field, &self.operation) {
+            (field, _) if field == "artifacts" || ARTIFACTS_ALIASES.contains(&field) => {
+                if self.value.is_empty() {
+                    return Err(self.error(format!("the field '{}' requires a value", self.field)));
+                }
+                if let Operation::Set = self.operation {
+                    artifact.artifacts.clear();
+                }
+                artifact.artifacts.push(self.value.to_string());
+            }
+            ("tags", Operation::Set) => artifact.tags = self.optional().map(|tag| vec![tag]),
+            ("tags", Operation::Append) => artifact
+                .tags
+                .get_or_insert_with(Vec::new)
+                .push(self.value.to_string()),
+            ("description", Operation::Set) => artifact.description = self.optional(),
+            ("regex", Operation::Set) => artifact.regex = self.optional(),
+            ("timeout", Operation::Set) => {
+                artifact.timeout = match self.optional() {
+                    Some(timeout) => Some(timeout.parse().map_err(|e| {
+                        self.error(format!("the timeout should be a number of seconds, ERROR: {}", e))
+                    })?),
+                    None => None,
+                }
+            }
+            ("description" | "regex" | "timeout", Operation::Append) => {
+                return Err(self.error(format!(
+                    "'+=' is only supported by the list fields 'paths', 'queries', 'commands' & 'tags', use '=' to set the field '{}'",
+                    self.field
+                )))
+            }
+            _ => {
+                return Err(self.error(format!(
+                    "the field '{}' can not be overridden, the supported fields are 'paths', 'queries', 'commands', 'tags', 'description', 'regex' & 'timeout'",
+                    self.field
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply the configuration overrides to the artifacts of the configuration in order, an override that does not match any artifact is an error
+pub fn apply(config: &mut FennecConfig, overrides: &[String]) -> Result<(), FennecError> {
+    for raw in overrides {
+        let config_override = Override::parse(raw)?;
+        let pattern = [config_override.name.to_string()];
+        let mut matched = false;
+        for artifact in config
+            .artifacts
+            .iter_mut()
+            .filter(|artifact| artifact.name_matches(&pattern))
+        {
+            config_override.apply(artifact)?;
+            matched = true;
+        }
+        if !matched {
+            return Err(config_override.error(format!(
+                "no artifact matches the name '{}'",
+                config_override.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifact(name: &str) -> Artifact {
+        Artifact {
+            name: name.to_string(),
+            ..Artifact::default()
+        }
+    }
+
+    fn config() -> FennecConfig {
+        FennecConfig {
+            args: None,
+            profiles: None,
+            include: None,
+            osquery: None,
+            artifacts: vec![artifact("users"), artifact("linux.processes")],
+        }
+    }
+
+    #[test]
+    fn parse_splits_the_name_and_field() {
+        let parsed = Override::parse("artifact.linux.processes.tags+=volatile").unwrap();
+        assert_eq!(parsed.name, "linux.processes");
+        assert_eq!(parsed.field, "tags");
+        assert!(matches!(parsed.operation, Operation::Append));
+        assert_eq!(parsed.value, "volatile");
+
+        let parsed = Override::parse("artifact.*.timeout=").unwrap();
+        assert_eq!(parsed.name, "*");
+        assert!(matches!(parsed.operation, Operation::Set));
+        assert_eq!(parsed.value, "");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_overrides() {
+        for (raw, reason) in [
+            ("artifact.users.timeout", "the value is missing"),
+            ("output.format=csv", "only the artifacts fields"),
+            ("artifact.users=1", "the field name is missing"),
+        ] {
+            let err = Override::parse(raw).err().unwrap();
+            assert!(err.message.contains(reason), "{}", err.message);
+        }
+    }
+
+    #[test]
+    fn apply_sets_and_appends_fields() {
+        let mut users = artifact("users");
+        Override::parse("artifact.users.queries=select 1")
+            .unwrap()
+            .apply(&mut users)
+            .unwrap();
+        Override::parse("artifact.users.queries+=select 2")
+            .unwrap()
+            .apply(&mut users)
+            .unwrap();
+        assert_eq!(users.artifacts, vec!["select 1", "select 2"]);
+
+        Override::parse("artifact.users.tags+=volatile")
+            .unwrap()
+            .apply(&mut users)
+            .unwrap();
+        assert_eq!(users.tags, Some(vec![String::from("volatile")]));
+
+        Override::parse("artifact.users.timeout=30")
+            .unwrap()
+            .apply(&mut users)
+            .unwrap();
+        assert_eq!(users.timeout, Some(30));
+        Override::parse("artifact.users.description=")
+            .unwrap()
+            .apply(&mut users)
+            .unwrap();
+        assert_eq!(users.description, None);
+    }
+
+    #[test]
+    fn apply_rejects_invalid_values() {
+        let mut users = artifact("users");
+        for (raw, reason) in [
+            ("artifact.users.timeout=soon", "number of seconds"),
+            ("artifact.users.description+=more", "'+=' is only supported"),
+            ("artifact.users.paths=", "requires a value"),
+            ("artifact.users.type=parse", "can not be overridden"),
+        ] {
+            let err = Override::parse(raw)
+                .unwrap()
+                .apply(&mut users)
+                .err()
+                .unwrap();
+            assert!(err.message.contains(reason), "{}", err.message);
+        }
+        assert_eq!(users.timeout, Artifact::default().timeout);
+    }
+
+    #[test]
+    fn apply_matches_artifact_names() {
+        let mut config = config();
+        apply(&mut config, &[String::from("artifact.*.timeout=5")]).unwrap();
+        assert!(config.artifacts.iter().all(|a| a.timeout == Some(5)));
+
+        let err = apply(&mut config, &[String::from("artifact.missing.timeout=5")])
+            .err()
+            .unwrap();
+        assert!(err
+            .message
+            .contains("no artifact matches the name 'missing'"));
+    }
+}
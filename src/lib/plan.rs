@@ -0,0 +1,44 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// What would happen to an artifact during triage:
+/// * Run: the artifact would run
+/// * Filtered: the artifact is not selected by the names, excluded names or tags filters
+/// * Skipped: the conditions in the field `when` are false
+pub enum PlanStatus {
+    Run,
+    Filtered,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+/// A file that would be collected or parsed
+pub struct PlannedFile {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+/// Describes what an artifact would do without running it
+pub struct ArtifactPlan {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub artifact_type: String,
+    pub status: PlanStatus,
+    pub reason: Option<String>,
+    /// The SQL queries, commands or glob patterns of the artifact
+    pub artifacts: Vec<String>,
+    pub timeout: Option<u64>,
+    /// The files matching the glob patterns of `collection` & `parse` artifacts
+    pub files: Vec<PlannedFile>,
+    pub estimated_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+/// The result of `Fennec::plan`, lists what the configuration would collect
+pub struct TriagePlan {
+    pub artifacts: Vec<ArtifactPlan>,
+    /// Sum of the sizes of the collected files before compression, the parsed files are not copied to the package and the size of `query`, `command` & `parse` artifacts results can not be estimated
+    pub estimated_size: u64,
+}
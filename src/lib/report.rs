@@ -0,0 +1,135 @@
+use log::*;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// The result of running an artifact:
+/// * Success: the artifact finished without errors
+/// * Partial: the artifact wrote some results but errors were encountered or it was truncated
+/// * Failed: the artifact did not write any results because of errors
+/// * Skipped: the artifact did not run, check the field `reason`
+pub enum ArtifactStatus {
+    Success,
+    Partial,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+/// Describes what happened while running a single artifact
+pub struct ArtifactReport {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub artifact_type: String,
+    pub status: ArtifactStatus,
+    pub reason: Option<String>,
+    /// Number of records written, for `collection` artifacts this is the number of metadata records
+    pub rows: u64,
+    /// Number of files collected by `collection` artifacts
+    pub files: u64,
+    /// Number of bytes written to the output file before compression
+    pub bytes_written: u64,
+    /// Set when a `collection` artifact stopped collecting files because the package size budget was reached, check the field `reason`
+    pub truncated: bool,
+    /// Set when the `query` artifact ran using the native tables because osquery is not available
+    pub native: bool,
+    /// Number of times the osquery SQL queries of the `query` artifact were retried because osquery crashed or stopped answering
+    pub osquery_retries: u64,
+    pub duration_seconds: f64,
+    pub errors: Vec<String>,
+}
+
+impl ArtifactReport {
+    pub fn new(name: &str, artifact_type: String) -> Self {
+        Self {
+            name: name.to_string(),
+            artifact_type,
+            status: ArtifactStatus::Success,
+            reason: None,
+            rows: 0,
+            files: 0,
+            bytes_written: 0,
+            truncated: false,
+            native: false,
+            osquery_retries: 0,
+            duration_seconds: 0.0,
+            errors: vec![],
+        }
+    }
+
+    /// Log the error and add it to the report
+    pub fn error(&mut self, message: String) {
+        error!("{}", message);
+        self.errors.push(message);
+    }
+
+    /// Mark the artifact as skipped with the reason
+    pub fn skip(&mut self, reason: String) {
+        self.status = ArtifactStatus::Skipped;
+        self.reason = Some(reason);
+    }
+
+    /// Mark the artifact as truncated with the reason, the files not collected are not errors but the results are incomplete
+    pub fn truncate(&mut self, reason: String) {
+        warn!("{}", reason);
+        self.truncated = true;
+        self.reason = Some(reason);
+    }
+
+    /// Set the status from the errors encountered and the results written, skipped artifacts are not changed.
+    /// A truncated artifact is partial even if none of its files fit in the package size budget
+    pub fn finish(&mut self) {
+        if self.status == ArtifactStatus::Skipped {
+            return;
+        }
+        self.status = match (
+            self.errors.is_empty(),
+            self.truncated,
+            self.rows > 0 || self.files > 0,
+        ) {
+            (true, false, _) => ArtifactStatus::Success,
+            (_, true, _) | (false, false, true) => ArtifactStatus::Partial,
+            (false, false, false) => ArtifactStatus::Failed,
+        };
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// The result of `Fennec::triage`, also written to the output file as `triage_report.json`
+pub struct TriageReport {
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_seconds: f64,
+    pub success: usize,
+    pub partial: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Number of times osquery was restarted because it crashed or stopped answering
+    pub osquery_restarts: u64,
+    /// Errors that are not related to a single artifact, such as failing to start osquery
+    pub errors: Vec<String>,
+    pub artifacts: Vec<ArtifactReport>,
+}
+
+impl TriageReport {
+    /// Number of artifacts with the status
+    pub fn count(&self, status: ArtifactStatus) -> usize {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.status == status)
+            .count()
+    }
+
+    /// Number of artifacts truncated because the package size budget was reached
+    pub fn truncated(&self) -> usize {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.truncated)
+            .count()
+    }
+
+    /// Checks if no artifact failed or finished partially
+    pub fn is_complete(&self) -> bool {
+        self.failed == 0 && self.partial == 0
+    }
+}
@@ -0,0 +1,56 @@
+use std::iter;
+
+use schemars::{
+    schema::{ObjectValidation, RootSchema, Schema, SchemaObject},
+    schema_for,
+};
+
+use crate::{FennecConfig, ARTIFACTS_ALIASES};
+
+/// Returns the JSON schema of the configuration generated from the configuration types, used by editors to validate the configurations.
+/// Unknown fields are not allowed by the schema so typos are reported by the editors, Fennec ignores them
+pub fn schema() -> RootSchema {
+    let mut root = schema_for!(FennecConfig);
+    deny_unknown_fields(&mut root.schema);
+    for definition in root.definitions.values_mut() {
+        if let Schema::Object(definition) = definition {
+            deny_unknown_fields(definition);
+        }
+    }
+    if let Some(Schema::Object(artifact)) = root.definitions.get_mut("Artifact") {
+        add_artifacts_aliases(artifact);
+    }
+    root
+}
+
+fn deny_unknown_fields(schema: &mut SchemaObject) {
+    if let Some(object) = &mut schema.object {
+        object.additional_properties = Some(Box::new(Schema::Bool(false)));
+    }
+}
+
+/// The aliases of the field `artifacts` are not included by `schemars`, exactly one of the names is required
+fn add_artifacts_aliases(artifact: &mut SchemaObject) {
+    let object = match &mut artifact.object {
+        Some(object) => object,
+        None => return,
+    };
+    let field = match object.properties.get("artifacts") {
+        Some(field) => field.clone(),
+        None => return,
+    };
+    object.required.remove("artifacts");
+
+    let mut one_of = vec![];
+    for name in iter::once("artifacts").chain(ARTIFACTS_ALIASES) {
+        object.properties.insert(name.to_string(), field.clone());
+        one_of.push(Schema::Object(SchemaObject {
+            object: Some(Box::new(ObjectValidation {
+                required: [name.to_string()].into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }));
+    }
+    artifact.subschemas().one_of = Some(one_of);
+}
@@ -0,0 +1,710 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Error, ErrorKind, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use flate2::{write::GzEncoder, Compression};
+use log::*;
+use serde::{Deserialize, Serialize};
+use tar::{Builder, EntryType, Header};
+use zip::{
+    write::{SimpleFileOptions, StreamWriter},
+    CompressionMethod, ZipWriter,
+};
+
+use crate::manifest::HashingWriter;
+use crate::staging::Staging;
+
+/// The destination of the artifact package. Entries are written one at a time, `start_entry` starts a new entry
+/// and the following writes are the content of that entry until the next entry is started.
+pub trait OutputSink: Write + Send {
+    /// Start a new file entry with the name `name`
+    fn start_entry(&mut self, name: &str) -> Result<(), Error>;
+    /// Start a new file entry with the name `name` when the size of its content is known before writing it, check
+    /// `is_streaming` for the outputs that require exactly `size` bytes. Default calls `start_entry`
+    fn start_sized_entry(&mut self, name: &str, _size: u64) -> Result<(), Error> {
+        self.start_entry(name)
+    }
+    /// Returns `true` if the current entry is written directly to the output, exactly the `size` passed to `start_sized_entry`
+    /// should be written to it. Default `false`
+    fn is_streaming(&self) -> bool {
+        false
+    }
+    /// Called after the content of the entry is written, Default flushes the written data
+    fn end_entry(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+    /// Add a directory entry with the name `name`
+    fn add_directory(&mut self, name: &str) -> Result<(), Error>;
+    /// Finish the package, no entries can be added after calling it
+    fn finish(&mut self) -> Result<(), Error>;
+}
+
+/// Writes the artifact package to a ZIP file, this is the default output
+pub struct ZipSink<W: Write + Seek + Send> {
+    writer: Option<ZipWriter<W>>,
+    options: SimpleFileOptions,
+}
+
+impl<W: Write + Seek + Send> ZipSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_writer(ZipWriter::new(writer))
+    }
+
+    fn with_writer(writer: ZipWriter<W>) -> Self {
+        Self {
+            writer: Some(writer),
+            options: SimpleFileOptions::default()
+                .large_file(true)
+                .compression_method(CompressionMethod::Deflated),
+        }
+    }
+
+    /// Allows setting `FileOptions` of the ZipWriter at runtime, Default is deflate compression with large file support
+    pub fn set_options(mut self, options: SimpleFileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn writer(&mut self) -> Result<&mut ZipWriter<W>, Error> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| Error::other("the ZIP file is already finished"))
+    }
+}
+
+impl<W: Write + Send> ZipSink<StreamWriter<W>> {
+    /// Writes the ZIP file to a writer that does not support seeking such as `stdout`, the sizes and CRC of each entry are
+    /// written in a data descriptor after the entry content
+    pub fn stream(writer: W) -> Self {
+        Self::with_writer(ZipWriter::new_stream(writer))
+    }
+}
+
+impl<W: Write + Seek + Send> Write for ZipSink<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.writer()?.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer()?.flush()
+    }
+}
+
+impl<W: Write + Seek + Send> OutputSink for ZipSink<W> {
+    fn start_entry(&mut self, name: &str) -> Result<(), Error> {
+        let options = self.options;
+        Ok(self.writer()?.start_file(name, options)?)
+    }
+
+    fn add_directory(&mut self, name: &str) -> Result<(), Error> {
+        let options = self.options;
+        Ok(self.writer()?.add_directory(name, options)?)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match self.writer.take() {
+            Some(writer) => writer.finish()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes the artifact package as a directory tree, each entry is written to a file under the root directory
+pub struct DirectorySink {
+    root: PathBuf,
+    current: Option<BufWriter<File>>,
+}
+
+impl DirectorySink {
+    /// Creates the root directory if it does not exist
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, Error> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self {
+            root: root.as_ref().to_path_buf(),
+            current: None,
+        })
+    }
+
+    /// Returns the path of the entry under the root directory, absolute entry names are made relative and `..` is rejected
+    fn entry_path(&self, name: &str) -> Result<PathBuf, Error> {
+        let mut path = self.root.clone();
+        for component in Path::new(name).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+                Component::ParentDir => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("the entry name '{}' is outside the output directory", name),
+                    ))
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    fn close_entry(&mut self) -> Result<(), Error> {
+        if let Some(mut current) = self.current.take() {
+            current.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for DirectorySink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match &mut self.current {
+            Some(current) => current.write(buf),
+            None => Err(Error::other("no entry started in the output directory")),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.current {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl OutputSink for DirectorySink {
+    fn start_entry(&mut self, name: &str) -> Result<(), Error> {
+        self.close_entry()?;
+        let path = self.entry_path(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.current = Some(BufWriter::new(File::create(path)?));
+        Ok(())
+    }
+
+    fn end_entry(&mut self) -> Result<(), Error> {
+        self.close_entry()
+    }
+
+    fn add_directory(&mut self, name: &str) -> Result<(), Error> {
+        self.close_entry()?;
+        fs::create_dir_all(self.entry_path(name)?)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.close_entry()
+    }
+}
+
+/// The writer of `TarSink`, optionally gzip compressed
+enum TarWriter<W: Write> {
+    Plain(W),
+    Gzip(Box<GzEncoder<W>>),
+}
+
+impl<W: Write> Write for TarWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            TarWriter::Plain(w) => w.write(buf),
+            TarWriter::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            TarWriter::Plain(w) => w.flush(),
+            TarWriter::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+/// The entry being written to `TarSink`
+enum TarEntry {
+    /// The size is not known, the content is staged until the entry ends
+    Staged(String, Staging),
+    /// The header is already written, the content is written directly to the tar stream
+    Streamed {
+        name: String,
+        size: u64,
+        written: u64,
+    },
+}
+
+/// Writes the artifact package to a tar or tar.gz stream. The tar header contains the size of the entry so the content
+/// of an entry started with `start_entry` is staged (in memory, then in an unlinked temporary file once it reaches the staging limit)
+/// until the next entry is started. The entries started with `start_sized_entry` larger than the staging limit such as the large
+/// collected files are streamed instead, their content should be exactly the size passed to `start_sized_entry`
+pub struct TarSink<W: Write + Send> {
+    builder: Builder<TarWriter<W>>,
+    current: Option<TarEntry>,
+    staging_limit: usize,
+}
+
+impl<W: Write + Send> TarSink<W> {
+    /// Writes an uncompressed tar stream
+    pub fn new(writer: W) -> Self {
+        Self::with_writer(TarWriter::Plain(writer))
+    }
+
+    /// Writes a gzip compressed tar stream
+    pub fn gzip(writer: W) -> Self {
+        Self::with_writer(TarWriter::Gzip(Box::new(GzEncoder::new(
+            writer,
+            Compression::default(),
+        ))))
+    }
+
+    fn with_writer(writer: TarWriter<W>) -> Self {
+        Self {
+            builder: Builder::new(writer),
+            current: None,
+            staging_limit: 1024 * 1024 * 64,
+        }
+    }
+
+    /// Sets the size in bytes an entry can reach in memory before it is staged in a temporary file, Default `1024 * 1024 * 64 (64MiB)`
+    pub fn set_staging_limit(mut self, size: usize) -> Self {
+        self.staging_limit = size;
+        self
+    }
+
+    fn header(entry_type: EntryType, mode: u32, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        );
+        header
+    }
+
+    /// Append the staged entry to the tar stream, or pad the streamed entry to the end of its last block
+    fn close_entry(&mut self) -> Result<(), Error> {
+        match self.current.take() {
+            Some(TarEntry::Staged(name, data)) => {
+                let mut header = Self::header(EntryType::Regular, 0o644, data.len());
+                let reader = data.into_reader()?;
+                self.builder
+                    .append_data(&mut header, name.trim_start_matches('/'), reader)?;
+                trace!("Appended the entry '{}' to the tar stream", name);
+            }
+            Some(TarEntry::Streamed {
+                name,
+                size,
+                written,
+            }) => {
+                if written < size {
+                    warn!(
+                        "Only '{}' of the '{}' bytes of the entry '{}' were written, the entry is padded with zeros",
+                        written, size, name
+                    );
+                }
+                let padding = (512 - size % 512) % 512 + (size - written.min(size));
+                std::io::copy(
+                    &mut std::io::repeat(0).take(padding),
+                    self.builder.get_mut(),
+                )?;
+                trace!("Streamed the entry '{}' to the tar stream", name);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Write for TarSink<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match &mut self.current {
+            Some(TarEntry::Staged(_, data)) => data.write(buf),
+            Some(TarEntry::Streamed {
+                name,
+                size,
+                written,
+            }) => {
+                if *written + buf.len() as u64 > *size {
+                    return Err(Error::other(format!(
+                        "the entry '{}' is larger than its size '{}'",
+                        name, size
+                    )));
+                }
+                let n = self.builder.get_mut().write(buf)?;
+                *written += n as u64;
+                Ok(n)
+            }
+            None => Err(Error::other("no entry started in the tar stream")),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> OutputSink for TarSink<W> {
+    fn start_entry(&mut self, name: &str) -> Result<(), Error> {
+        self.close_entry()?;
+        self.current = Some(TarEntry::Staged(
+            name.to_string(),
+            Staging::new(self.staging_limit),
+        ));
+        Ok(())
+    }
+
+    fn start_sized_entry(&mut self, name: &str, size: u64) -> Result<(), Error> {
+        if size <= self.staging_limit as u64 {
+            return self.start_entry(name);
+        }
+        self.close_entry()?;
+        // Only the header (and the GNU long name entry if needed) is appended, the content is written by the following writes
+        let mut header = Self::header(EntryType::Regular, 0o644, size);
+        self.builder
+            .append_data(&mut header, name.trim_start_matches('/'), std::io::empty())?;
+        self.current = Some(TarEntry::Streamed {
+            name: name.to_string(),
+            size,
+            written: 0,
+        });
+        Ok(())
+    }
+
+    fn is_streaming(&self) -> bool {
+        matches!(self.current, Some(TarEntry::Streamed { .. }))
+    }
+
+    fn end_entry(&mut self) -> Result<(), Error> {
+        self.close_entry()?;
+        self.builder.get_mut().flush()
+    }
+
+    fn add_directory(&mut self, name: &str) -> Result<(), Error> {
+        self.close_entry()?;
+        let mut header = Self::header(EntryType::Directory, 0o755, 0);
+        self.builder
+            .append_data(&mut header, name.trim_start_matches('/'), std::io::empty())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.close_entry()?;
+        self.builder.finish()?;
+        match self.builder.get_mut() {
+            TarWriter::Plain(w) => w.flush(),
+            TarWriter::Gzip(w) => w.try_finish(),
+        }
+    }
+}
+
+/// Opens the output sink of a volume, the argument is the path of the volume
+pub type VolumeOpener = Box<dyn FnMut(&Path) -> Result<Box<dyn OutputSink>, Error> + Send>;
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A single volume in the volumes index
+pub struct Volume {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Lists the volumes of an artifact package split by `VolumeSink`, written next to the volumes when the package is finished
+pub struct VolumeIndex {
+    pub volumes: Vec<Volume>,
+}
+
+impl VolumeIndex {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        serde_json::from_reader(File::open(path)?).map_err(Error::other)
+    }
+}
+
+/// Splits the artifact package into numbered volumes (ex. `host.001.zip`, `host.002.zip`) so every volume is a complete package.
+/// A new volume is started before an entry started with `start_sized_entry` that does not fit in the current volume, and at the
+/// first entry after the current volume reaches the maximum size. A volume exceeds the maximum size when its only entry is larger
+/// than the maximum size or by the size of its last entry if the size of the entry was not known when it started
+pub struct VolumeSink {
+    path: PathBuf,
+    max_size: u64,
+    open: VolumeOpener,
+    current: Option<Box<dyn OutputSink>>,
+    full: bool,
+    volumes: Vec<PathBuf>,
+}
+
+impl VolumeSink {
+    /// `path` is the name of the package without the volume number, the volumes are opened using `open`
+    pub fn new(path: impl AsRef<Path>, max_size: u64, open: VolumeOpener) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            max_size,
+            open,
+            current: None,
+            full: false,
+            volumes: vec![],
+        }
+    }
+
+    /// Split the package name into the name without the extension and the extension (ex. `host` & `.zip.age`)
+    fn split_extension(path: &Path) -> (String, String) {
+        let name = path.to_string_lossy().to_string();
+        for extension in [".tar.gz", ".tar", ".zip"] {
+            for suffix in [format!("{}.age", extension), extension.to_string()] {
+                if let Some(stem) = name.strip_suffix(&suffix) {
+                    return (stem.to_string(), suffix);
+                }
+            }
+        }
+        (name, String::new())
+    }
+
+    /// Returns the path of the volume number `volume`, the number is added before the package extension
+    pub fn volume_path(path: &Path, volume: usize) -> PathBuf {
+        let (stem, extension) = Self::split_extension(path);
+        PathBuf::from(format!("{}.{:03}{}", stem, volume, extension))
+    }
+
+    /// Returns the path of the volumes index, ex. `host.index.json` for `host.zip`
+    pub fn index_path(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.index.json", Self::split_extension(path).0))
+    }
+
+    fn finish_volume(&mut self) -> Result<(), Error> {
+        if let Some(mut current) = self.current.take() {
+            current.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the current volume, a new volume is started when the current volume reached the maximum size
+    fn next_entry(&mut self) -> Result<&mut Box<dyn OutputSink>, Error> {
+        if self.full || self.current.is_none() {
+            self.finish_volume()?;
+            let path = Self::volume_path(&self.path, self.volumes.len() + 1);
+            info!(
+                "Writing the artifact package volume '{}'",
+                path.to_string_lossy()
+            );
+            self.current = Some((self.open)(&path)?);
+            self.volumes.push(path);
+            self.full = false;
+        }
+        match &mut self.current {
+            Some(current) => Ok(current),
+            None => Err(Error::other("no volume is open")),
+        }
+    }
+
+    /// Returns the size of the current volume file
+    fn volume_size(&self) -> Result<u64, Error> {
+        match self.volumes.last() {
+            Some(volume) => Ok(fs::metadata(volume)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Mark the current volume as full if it reached the maximum size
+    fn check_size(&mut self) -> Result<(), Error> {
+        self.full = self.volume_size()? >= self.max_size;
+        Ok(())
+    }
+
+    fn write_index(&self) -> Result<(), Error> {
+        let mut volumes = vec![];
+        for path in self.volumes.iter() {
+            let mut writer = HashingWriter::new(std::io::sink());
+            std::io::copy(&mut File::open(path)?, &mut writer)?;
+            let (size, sha256) = writer.finalize();
+            volumes.push(Volume {
+                path: path.to_string_lossy().to_string(),
+                size,
+                sha256,
+            });
+        }
+        let index_path = Self::index_path(&self.path);
+        serde_json::to_writer_pretty(File::create(&index_path)?, &VolumeIndex { volumes })
+            .map_err(Error::other)?;
+        info!(
+            "Wrote '{}' volume(s) to the index '{}'",
+            self.volumes.len(),
+            index_path.to_string_lossy()
+        );
+        Ok(())
+    }
+}
+
+impl Write for VolumeSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match &mut self.current {
+            Some(current) => current.write(buf),
+            None => Err(Error::other("no entry started in the volume")),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.current {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl OutputSink for VolumeSink {
+    fn start_entry(&mut self, name: &str) -> Result<(), Error> {
+        self.next_entry()?.start_entry(name)
+    }
+
+    /// A new volume is started if the entry does not fit in the current volume, the volumes are opened with their first entry
+    /// so an entry larger than the maximum size is written to its own volume
+    fn start_sized_entry(&mut self, name: &str, size: u64) -> Result<(), Error> {
+        if self.current.is_some() && self.volume_size()?.saturating_add(size) > self.max_size {
+            self.full = true;
+        }
+        self.next_entry()?.start_sized_entry(name, size)
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|current| current.is_streaming())
+    }
+
+    /// The size of the volume is checked after each entry, the next entry starts a new volume if the maximum size is reached
+    fn end_entry(&mut self) -> Result<(), Error> {
+        if let Some(current) = &mut self.current {
+            current.end_entry()?;
+        }
+        self.check_size()
+    }
+
+    /// The directory entries count towards the size of the volume like the file entries
+    fn add_directory(&mut self, name: &str) -> Result<(), Error> {
+        let current = self.next_entry()?;
+        current.add_directory(name)?;
+        // Flushes the entry so the size of the volume includes it
+        current.end_entry()?;
+        self.check_size()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.finish_volume()?;
+        self.write_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_path_stays_under_the_root() {
+        let root = std::env::temp_dir().join(format!("fennec-sink-test-{}", std::process::id()));
+        let sink = DirectorySink::new(&root).unwrap();
+        assert_eq!(
+            sink.entry_path("/var/log/syslog").unwrap(),
+            root.join("var/log/syslog")
+        );
+        assert_eq!(
+            sink.entry_path("./users.csv").unwrap(),
+            root.join("users.csv")
+        );
+        let err = sink.entry_path("/var/../../etc/passwd").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        fs::remove_dir(&root).unwrap();
+    }
+
+    #[test]
+    fn tar_large_sized_entry_is_streamed() {
+        let mut sink = TarSink::new(vec![]).set_staging_limit(4);
+        sink.start_sized_entry("small.bin", 4).unwrap();
+        assert!(!sink.is_streaming());
+        sink.write_all(b"0123").unwrap();
+        sink.start_sized_entry("big.bin", 10).unwrap();
+        assert!(sink.is_streaming());
+        sink.write_all(b"0123456789").unwrap();
+        assert!(sink.write_all(b"x").is_err());
+        sink.end_entry().unwrap();
+        sink.finish().unwrap();
+        let data = match sink.builder.into_inner().unwrap() {
+            TarWriter::Plain(data) => data,
+            TarWriter::Gzip(_) => unreachable!(),
+        };
+
+        let mut archive = tar::Archive::new(data.as_slice());
+        let entries: Vec<(PathBuf, Vec<u8>)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = vec![];
+                entry.read_to_end(&mut content).unwrap();
+                (entry.path().unwrap().to_path_buf(), content)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (PathBuf::from("small.bin"), b"0123".to_vec()),
+                (PathBuf::from("big.bin"), b"0123456789".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn volume_path_keeps_the_package_extension() {
+        for (path, volume, expected) in [
+            ("/tmp/host.zip", 1, "/tmp/host.001.zip"),
+            ("/tmp/host.tar.gz", 2, "/tmp/host.002.tar.gz"),
+            ("/tmp/host.tar.gz.age", 12, "/tmp/host.012.tar.gz.age"),
+            ("/tmp/host.v1.tar", 3, "/tmp/host.v1.003.tar"),
+            ("/tmp/host", 1, "/tmp/host.001"),
+        ] {
+            assert_eq!(
+                VolumeSink::volume_path(Path::new(path), volume),
+                PathBuf::from(expected)
+            );
+        }
+        assert_eq!(
+            VolumeSink::split_extension(Path::new("host.zip.age")),
+            (String::from("host"), String::from(".zip.age"))
+        );
+        assert_eq!(
+            VolumeSink::index_path(Path::new("/tmp/host.zip")),
+            PathBuf::from("/tmp/host.index.json")
+        );
+    }
+
+    #[test]
+    fn volume_is_started_before_an_entry_that_does_not_fit() {
+        let root = std::env::temp_dir().join(format!("fennec-volume-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut sink = VolumeSink::new(
+            root.join("host.tar"),
+            200 * 1024,
+            Box::new(|path: &Path| {
+                Ok(
+                    Box::new(TarSink::new(File::create(path)?).set_staging_limit(1024))
+                        as Box<dyn OutputSink>,
+                )
+            }),
+        );
+        for (name, size) in [
+            ("a.bin", 150),
+            ("b.bin", 150),
+            ("c.bin", 20),
+            ("d.bin", 300),
+        ] {
+            sink.start_sized_entry(name, size * 1024).unwrap();
+            sink.write_all(&vec![0; size as usize * 1024]).unwrap();
+            sink.end_entry().unwrap();
+        }
+        sink.finish().unwrap();
+
+        let index = VolumeIndex::from_path(root.join("host.index.json")).unwrap();
+        let sizes: Vec<u64> = index.volumes.iter().map(|v| v.size / 1024).collect();
+        // `b.bin` & `c.bin` share a volume, `d.bin` is larger than the maximum size so it is alone in its volume
+        assert_eq!(index.volumes.len(), 3, "{:?}", sizes);
+        assert!(index.volumes[..2].iter().all(|v| v.size <= 200 * 1024));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
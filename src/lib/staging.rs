@@ -0,0 +1,112 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Cursor, Error, Read, Seek, SeekFrom, Write},
+    os::unix::fs::OpenOptionsExt,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::*;
+
+static STAGING_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Holds the output of an artifact until it is written to the artifact package. The data is kept in memory
+/// and moved to an unlinked temporary file once it grows beyond the staging limit.
+pub struct Staging {
+    memory: Vec<u8>,
+    file: Option<File>,
+    limit: usize,
+    len: u64,
+}
+
+impl Staging {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            memory: vec![],
+            file: None,
+            limit,
+            len: 0,
+        }
+    }
+
+    /// Number of bytes staged so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!(
+            ".fennec-{}-{}.staging",
+            process::id(),
+            STAGING_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let mut file = OpenOptions::new()
+            .mode(0o600)
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // The file is only accessed using the handle, remove it right away so nothing is left behind
+        fs::remove_file(&path)?;
+        file.write_all(&self.memory)?;
+        debug!(
+            "Staged data reached '{}' bytes, moved it to a temporary file",
+            self.memory.len()
+        );
+        self.memory = vec![];
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Consume the staged data and return a reader positioned at its beginning
+    pub fn into_reader(self) -> Result<Box<dyn Read>, Error> {
+        match self.file {
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(file))
+            }
+            None => Ok(Box::new(Cursor::new(self.memory))),
+        }
+    }
+}
+
+impl Write for Staging {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.file.is_none() && self.memory.len() + buf.len() > self.limit {
+            self.spill()?;
+        }
+        let n = match &mut self.file {
+            Some(file) => file.write(buf)?,
+            None => self.memory.write(buf)?,
+        };
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A single file in the artifact package that is staged before it is written
+pub struct StagedEntry {
+    pub name: String,
+    pub data: Staging,
+    /// Headers used for CSV output instead of the keys of the first record
+    pub csv_headers: Option<Vec<String>>,
+    pub csv_headers_printed: bool,
+}
+
+impl StagedEntry {
+    pub fn new(name: String, limit: usize) -> Self {
+        Self {
+            name,
+            data: Staging::new(limit),
+            csv_headers: None,
+            csv_headers_printed: false,
+        }
+    }
+}
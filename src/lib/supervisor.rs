@@ -0,0 +1,399 @@
+use std::{
+    fs::{self, DirBuilder},
+    io::{Error, ErrorKind},
+    os::unix::{fs::DirBuilderExt, net::UnixStream},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, RwLock, RwLockReadGuard,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::*;
+use osquery_rs::OSQuery;
+
+/// Timeout in seconds of the query used to check if osquery is still answering
+const PING_TIMEOUT: u64 = 5;
+
+/// The flags osquery is spawned with, the flags of the configuration and the command line are added after them so they override them
+const DEFAULT_FLAGS: &[&str] = &[
+    "--disable_database",
+    "--disable_watchdog",
+    "--disable_logging",
+    "--ephemeral",
+    "--config_path=/dev/null",
+];
+
+/// Send a query to the osquery listening on the extension socket, returns the error if osquery does not answer it
+pub(crate) fn ping(socket: &str, timeout: u64) -> Result<(), String> {
+    let client = OSQuery::new().set_socket(socket).set_timeout(timeout);
+    match client.query(String::from("select 1")) {
+        Ok(res) => match res.status {
+            Some(status) if status.code == Some(0) => Ok(()),
+            status => Err(status
+                .and_then(|status| status.message)
+                .unwrap_or_else(|| String::from("the query did not return a status"))),
+        },
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Owns the osquery instance used by the query artifacts and restarts it when it crashes or stops answering, a running daemon
+/// Fennec attached to is never restarted. The workers hold a read lock while querying, a restart waits for the running queries then replaces the instance
+pub struct OSQuerySupervisor {
+    binary_path: String,
+    flags: Vec<String>,
+    extensions: Vec<String>,
+    timeout: u64,
+    instance: RwLock<Option<OSQuery>>,
+    /// Set when the instance is a running daemon Fennec did not spawn
+    attached: bool,
+    /// The osquery process spawned by Fennec, `None` when attached to a running daemon
+    child: Mutex<Option<Child>>,
+    /// The private directory of the extension socket & the extensions list of the spawned osquery, created by the first spawn
+    dir: Mutex<Option<PathBuf>>,
+    generation: AtomicUsize,
+    restarts: AtomicU64,
+}
+
+impl OSQuerySupervisor {
+    /// Supervise the instance of the running osquery daemon if set, otherwise `start` spawns osquery using the flags & extensions
+    pub fn new(
+        binary_path: &str,
+        timeout: u64,
+        flags: Vec<String>,
+        extensions: Vec<String>,
+        instance: Option<OSQuery>,
+    ) -> Self {
+        Self {
+            binary_path: binary_path.to_string(),
+            flags,
+            extensions,
+            timeout,
+            attached: instance.is_some(),
+            instance: RwLock::new(instance),
+            child: Mutex::new(None),
+            dir: Mutex::new(None),
+            generation: AtomicUsize::new(0),
+            restarts: AtomicU64::new(0),
+        }
+    }
+
+    /// Spawn osquery if it is not running
+    pub fn start(&self) -> Result<(), Error> {
+        let mut instance = match self.instance.write() {
+            Ok(instance) => instance,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if instance.is_none() {
+            *instance = Some(self.spawn()?);
+        }
+        Ok(())
+    }
+
+    /// Returns the current instance with its generation, the generation is passed to `restart` if the instance stops answering.
+    /// The returned guard should be dropped before calling `restart`
+    pub fn instance(&self) -> (usize, RwLockReadGuard<'_, Option<OSQuery>>) {
+        let instance = match self.instance.read() {
+            Ok(instance) => instance,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        (self.generation.load(Ordering::SeqCst), instance)
+    }
+
+    /// Check if osquery answers a query
+    pub fn is_alive(&self, instance: &OSQuery) -> bool {
+        ping(&instance.get_socket(), self.timeout.min(PING_TIMEOUT)).is_ok()
+    }
+
+    /// Number of times osquery was restarted
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::SeqCst)
+    }
+
+    /// Replace the instance of the generation with a new osquery instance, the instance is only restarted once when
+    /// multiple workers report it. An attached daemon is kept and the queries are retried on the same socket.
+    /// Returns `false` if osquery is not running anymore
+    pub fn restart(&self, generation: usize) -> bool {
+        if self.attached {
+            warn!("The running osquery daemon is not restarted because Fennec did not spawn it");
+            return true;
+        }
+        let mut instance = match self.instance.write() {
+            Ok(instance) => instance,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return instance.is_some();
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        *instance = None;
+        self.stop();
+
+        match self.spawn() {
+            Ok(new) => {
+                let restarts = self.restarts.fetch_add(1, Ordering::SeqCst) + 1;
+                info!(
+                    "Restarted osquery '{}' after it stopped answering, restarts: '{}'",
+                    self.binary_path, restarts
+                );
+                *instance = Some(new);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "Unable to restart osquery '{}', the remaining queries use the native tables, ERROR: {}",
+                    self.binary_path, e
+                );
+                false
+            }
+        }
+    }
+
+    /// The private directory (mode `0700`) of the spawned osquery files, the paths in it can not be replaced by other users
+    fn dir(&self) -> Result<PathBuf, Error> {
+        let mut dir = match self.dir.lock() {
+            Ok(dir) => dir,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(dir) = dir.as_ref() {
+            return Ok(dir.clone());
+        }
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        for attempt in 0..16 {
+            let path = std::env::temp_dir().join(format!(
+                "fennec-osquery-{}-{}",
+                process::id(),
+                nanos.wrapping_add(attempt)
+            ));
+            // Fails if the path exists, even as a symlink
+            match DirBuilder::new().mode(0o700).create(&path) {
+                Ok(_) => {
+                    *dir = Some(path.clone());
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::other(
+            "unable to create a private directory for the osquery socket",
+        ))
+    }
+
+    /// The extension socket of the spawned osquery
+    fn socket(dir: &Path) -> PathBuf {
+        dir.join("osquery.em")
+    }
+
+    /// The file listing the extensions loaded by the spawned osquery
+    fn autoload_path(dir: &Path) -> PathBuf {
+        dir.join("extensions.load")
+    }
+
+    /// Spawn osquery with the flags then wait for its extension socket and its extensions
+    fn spawn(&self) -> Result<OSQuery, Error> {
+        let dir = self.dir()?;
+        let socket = Self::socket(&dir);
+        // A socket left by a crashed osquery, only Fennec can write to the directory
+        if socket.exists() {
+            fs::remove_file(&socket)?;
+        }
+        let socket = socket.to_string_lossy().to_string();
+
+        let mut args = vec![format!("--extensions_socket={}", socket)];
+        args.extend(DEFAULT_FLAGS.iter().map(|flag| flag.to_string()));
+        if !self.extensions.is_empty() {
+            let autoload = Self::autoload_path(&dir);
+            fs::write(&autoload, self.extensions.join("\n"))?;
+            args.push(format!(
+                "--extensions_autoload={}",
+                autoload.to_string_lossy()
+            ));
+        }
+        args.extend(self.flags.iter().cloned());
+        debug!(
+            "Spawning osquery '{}' with the flags '{}'",
+            self.binary_path,
+            args.join(" ")
+        );
+
+        let mut child = Command::new(&self.binary_path)
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let started = Instant::now();
+        let timeout = Duration::from_secs(self.timeout);
+        while UnixStream::connect(&socket).is_err() {
+            let exited = child.try_wait()?;
+            if exited.is_some() || started.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                let message = match exited {
+                    Some(status) => format!(
+                        "osquery exited with '{}' before creating the socket '{}', check the osquery flags",
+                        status, socket
+                    ),
+                    None => format!(
+                        "osquery did not create the socket '{}' after '{}' seconds",
+                        socket, self.timeout
+                    ),
+                };
+                return Err(Error::other(message));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        match self.child.lock() {
+            Ok(mut spawned) => *spawned = Some(child),
+            Err(poisoned) => *poisoned.into_inner() = Some(child),
+        }
+
+        let instance = OSQuery::new().set_socket(&socket).set_timeout(self.timeout);
+        self.wait_extensions(&instance, started + timeout);
+        Ok(instance)
+    }
+
+    /// The extensions register their tables after osquery starts, wait until they are registered so the first queries can use them
+    fn wait_extensions(&self, instance: &OSQuery, deadline: Instant) {
+        if self.extensions.is_empty() {
+            return;
+        }
+        let sql = String::from(
+            "select count(*) as count from osquery_extensions where type = 'extension'",
+        );
+        loop {
+            let registered = instance
+                .query(sql.clone())
+                .ok()
+                .and_then(|res| res.response)
+                .and_then(|rows| rows.first()?.get("count")?.parse::<usize>().ok())
+                .unwrap_or(0);
+            if registered >= self.extensions.len() {
+                info!(
+                    "Loaded the osquery extensions [{}]",
+                    self.extensions.join(", ")
+                );
+                return;
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    "Only '{}' of the osquery extensions [{}] were loaded, the queries using their tables fail. osquery only loads extensions ending with '.ext' that are not writable by other users (see '--allow_unsafe')",
+                    registered,
+                    self.extensions.join(", ")
+                );
+                return;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    /// Kill the spawned osquery and remove its socket & extensions list
+    fn stop(&self) {
+        let child = match self.child.lock() {
+            Ok(mut child) => child.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(mut child) = child {
+            if let Err(e) = child.kill().and_then(|_| child.wait()) {
+                debug!("Unable to stop osquery '{}', ERROR: {}", child.id(), e);
+            }
+        }
+        let dir = match self.dir.lock() {
+            Ok(dir) => dir.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        if let Some(dir) = dir {
+            for path in [Self::socket(&dir), Self::autoload_path(&dir)] {
+                if path.exists() {
+                    if let Err(e) = fs::remove_file(&path) {
+                        debug!(
+                            "Unable to remove '{}', ERROR: {}",
+                            path.to_string_lossy(),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for OSQuerySupervisor {
+    fn drop(&mut self) {
+        self.stop();
+        let dir = match self.dir.get_mut() {
+            Ok(dir) => dir.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(dir) = dir {
+            if let Err(e) = fs::remove_dir(&dir) {
+                debug!(
+                    "Unable to remove the directory '{}', ERROR: {}",
+                    dir.to_string_lossy(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attached_daemon_is_not_restarted() {
+        let supervisor = OSQuerySupervisor::new(
+            "/nonexistent/osqueryd",
+            1,
+            vec![],
+            vec![],
+            Some(OSQuery::new().set_socket("/nonexistent/osquery.em")),
+        );
+        assert!(supervisor.restart(0));
+        let (generation, instance) = supervisor.instance();
+        assert_eq!(generation, 0);
+        assert!(instance.is_some());
+        assert_eq!(supervisor.restarts(), 0);
+    }
+
+    #[test]
+    fn failed_restart_bumps_the_generation_once() {
+        let supervisor = OSQuerySupervisor::new("/nonexistent/osqueryd", 1, vec![], vec![], None);
+        assert!(supervisor.start().is_err());
+        let (generation, instance) = supervisor.instance();
+        assert_eq!(generation, 0);
+        assert!(instance.is_none());
+        drop(instance);
+
+        assert!(!supervisor.restart(0));
+        assert_eq!(supervisor.instance().0, 1);
+        // A worker reporting the old generation does not restart again
+        assert!(!supervisor.restart(0));
+        assert_eq!(supervisor.instance().0, 1);
+        assert_eq!(supervisor.restarts(), 0);
+    }
+
+    #[test]
+    fn private_directory_is_removed_on_drop() {
+        let supervisor = OSQuerySupervisor::new("/bin/false", 5, vec![], vec![], None);
+        let err = supervisor.start().unwrap_err();
+        assert!(err.to_string().contains("before creating the socket"));
+
+        let dir = supervisor.dir().unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o700
+        );
+        drop(supervisor);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    io::{Error, Read, Write},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::*;
+use nix::libc;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::FennecError;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+/// IO scheduling classes, only supported on Linux:
+/// * Idle: the process gets disk time only when no other process needs the disk
+/// * BestEffort: the lowest priority of the default scheduling class
+pub enum IoClass {
+    Idle,
+    BestEffort,
+}
+
+/// Sets the CPU scheduling priority of Fennec from `0` to `19` (lowest priority), inherited by the threads and
+/// processes started after calling it such as osquery and the commands of `command` artifacts
+pub fn set_niceness(niceness: i32) -> Result<(), FennecError> {
+    if !(0..=19).contains(&niceness) {
+        return Err(FennecError::config_error(format!(
+            "The niceness '{}' should be between 0 and 19",
+            niceness
+        )));
+    }
+    // SAFETY: setpriority does not access memory
+    let res = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, niceness) };
+    match res {
+        0 => {
+            info!(
+                "Set the CPU scheduling priority (niceness) to '{}'",
+                niceness
+            );
+            Ok(())
+        }
+        _ => Err(FennecError::config_error(format!(
+            "Unable to set the niceness to '{}', ERROR: {}",
+            niceness,
+            Error::last_os_error()
+        ))),
+    }
+}
+
+/// Sets the IO scheduling class of Fennec, inherited by the threads and processes started after calling it
+#[cfg(target_os = "linux")]
+pub fn set_io_class(class: IoClass) -> Result<(), FennecError> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    let ioprio = match class {
+        IoClass::BestEffort => (2 << IOPRIO_CLASS_SHIFT) | 7,
+        IoClass::Idle => 3 << IOPRIO_CLASS_SHIFT,
+    };
+    // SAFETY: ioprio_set does not access memory
+    let res = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+    match res {
+        0 => {
+            info!("Set the IO scheduling class to '{:?}'", class);
+            Ok(())
+        }
+        _ => Err(FennecError::config_error(format!(
+            "Unable to set the IO scheduling class to '{:?}', ERROR: {}",
+            class,
+            Error::last_os_error()
+        ))),
+    }
+}
+
+/// Sets the IO scheduling class of Fennec, inherited by the threads and processes started after calling it
+#[cfg(not(target_os = "linux"))]
+pub fn set_io_class(class: IoClass) -> Result<(), FennecError> {
+    Err(FennecError::config_error(format!(
+        "Setting the IO scheduling class to '{:?}' is only supported on Linux",
+        class
+    )))
+}
+
+/// Limits the number of bytes per second, shared by all the readers or writers using it
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            state: Mutex::new((0.0, Instant::now())),
+        }
+    }
+
+    /// The maximum number of bytes to process at once, keeps the rate smooth instead of a burst every second
+    fn chunk_size(&self) -> usize {
+        (self.bytes_per_second / 10).max(4096) as usize
+    }
+
+    /// Wait until `bytes` are allowed by the rate
+    fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut state = match self.state.lock() {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let (available, last) = &mut *state;
+            let now = Instant::now();
+            let rate = self.bytes_per_second as f64;
+            // At most one second of unused rate is kept
+            *available = (*available + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+            *last = now;
+            *available -= bytes as f64;
+            match *available < 0.0 {
+                true => Duration::from_secs_f64(-*available / rate),
+                false => Duration::ZERO,
+            }
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Reader limited by a `RateLimiter`
+pub struct ThrottledReader<'a, R: Read> {
+    inner: R,
+    limiter: &'a RateLimiter,
+}
+
+impl<'a, R: Read> ThrottledReader<'a, R> {
+    pub fn new(inner: R, limiter: &'a RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = buf.len().min(self.limiter.chunk_size());
+        let n = self.inner.read(&mut buf[..len])?;
+        self.limiter.acquire(n);
+        Ok(n)
+    }
+}
+
+/// Writer limited by a `RateLimiter`
+pub struct ThrottledWriter<'a, W: Write> {
+    inner: W,
+    limiter: &'a RateLimiter,
+}
+
+impl<'a, W: Write> ThrottledWriter<'a, W> {
+    pub fn new(inner: W, limiter: &'a RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let len = buf.len().min(self.limiter.chunk_size());
+        let n = self.inner.write(&buf[..len])?;
+        self.limiter.acquire(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
@@ -163,7 +163,7 @@ impl UploadArtifacts {
                             config
                         );
 
-                        return Ok(UploadArtifacts { config });
+                        Ok(UploadArtifacts { config })
                     }
                     "aws3" => {
                         let access_key = captures.name("access_key").unwrap().as_str().to_string();
@@ -190,7 +190,7 @@ impl UploadArtifacts {
                             config
                         );
 
-                        return Ok(UploadArtifacts { config });
+                        Ok(UploadArtifacts { config })
                     }
                     "scp" => {
                         let username = captures.name("username").unwrap().as_str().to_string();
@@ -218,22 +218,22 @@ impl UploadArtifacts {
                             config
                         );
 
-                        return Ok(UploadArtifacts { config });
+                        Ok(UploadArtifacts { config })
                     }
                     _ => {
-                        return Err(FennecError::upload_config_error("protocol not supported in upload artifacts. Supported protocol are s3 and aws3.".to_string()));
+                        Err(FennecError::upload_config_error("protocol not supported in upload artifacts. Supported protocol are s3 and aws3.".to_string()))
                     }
                 },
                 None => {
-                    return Err(FennecError::upload_config_error(
+                    Err(FennecError::upload_config_error(
                         "upload artifacts configurations format issue".to_string(),
-                    ));
+                    ))
                 }
             },
             None => {
-                return Err(FennecError::upload_config_error(
+                Err(FennecError::upload_config_error(
                     "upload artifacts configurations format issue".to_string(),
-                ));
+                ))
             }
         }
     }
@@ -287,32 +287,28 @@ impl UploadArtifacts {
                         ) {
                             Ok(status_code) => {
                                 if status_code == 200 {
-                                    return Ok(true);
+                                    Ok(true)
                                 } else {
-                                    return Err(FennecError::upload_error(format!(
+                                    Err(FennecError::upload_error(format!(
                                         "Unable to upload the object '{}' to the bucket '{}', ERROR: status code '{}'",
                                         &path.as_ref().file_name().unwrap().to_str().unwrap(),
                                         config.bucket_name,
                                         status_code
-                                    )));
+                                    )))
                                 }
                             }
-                            Err(e) => {
-                                return Err(FennecError::upload_error(format!(
-                                    "Unable to upload the object '{}' to the bucket '{}', ERROR: {}",
-                                    &path.as_ref().file_name().unwrap().to_str().unwrap(),
-                                    config.bucket_name,
-                                    e
-                                )));
-                            }
+                            Err(e) => Err(FennecError::upload_error(format!(
+                                "Unable to upload the object '{}' to the bucket '{}', ERROR: {}",
+                                &path.as_ref().file_name().unwrap().to_str().unwrap(),
+                                config.bucket_name,
+                                e
+                            ))),
                         }
                     }
-                    Err(e) => {
-                        return Err(FennecError::upload_error(format!(
-                            "Unable to connect to the endpoints '{}://{}:{}', ERROR: {}",
-                            config.protocol, config.hostname, config.port, e
-                        )));
-                    }
+                    Err(e) => Err(FennecError::upload_error(format!(
+                        "Unable to connect to the endpoints '{}://{}:{}', ERROR: {}",
+                        config.protocol, config.hostname, config.port, e
+                    ))),
                 }
             }
             UploadSupportedProtocols::AWS3(config) => {
@@ -364,34 +360,28 @@ impl UploadArtifacts {
                         ) {
                             Ok(status_code) => {
                                 if status_code == 200 {
-                                    return Ok(true);
+                                    Ok(true)
                                 } else {
-                                    return Err(FennecError::upload_error(format!(
+                                    Err(FennecError::upload_error(format!(
                                         "Unable to upload the object '{}' to the bucket '{}', ERROR: status code '{}'",
                                         &path.as_ref().file_name().unwrap().to_str().unwrap(),
                                         config.bucket_name,
                                         status_code
-                                    )));
+                                    )))
                                 }
                             }
-                            Err(e) => {
-                                return Err(FennecError::upload_error(format!(
-                                    "Unable to upload the object '{}' to the bucket '{}', ERROR: {}",
-                                    &path.as_ref().file_name().unwrap().to_str().unwrap(),
-                                    config.bucket_name,
-                                    e
-                                )));
-                            }
+                            Err(e) => Err(FennecError::upload_error(format!(
+                                "Unable to upload the object '{}' to the bucket '{}', ERROR: {}",
+                                &path.as_ref().file_name().unwrap().to_str().unwrap(),
+                                config.bucket_name,
+                                e
+                            ))),
                         }
                     }
-                    Err(e) => {
-                        return Err(FennecError::upload_error(
-                            format!(
-                                "Unable to connect to the AWS bucket '{}' in the regoin '{}', ERROR: {}",
-                                config.bucket_name, config.regoin, e
-                            ),
-                        ));
-                    }
+                    Err(e) => Err(FennecError::upload_error(format!(
+                        "Unable to connect to the AWS bucket '{}' in the regoin '{}', ERROR: {}",
+                        config.bucket_name, config.regoin, e
+                    ))),
                 }
             }
             UploadSupportedProtocols::SCP(config) => {
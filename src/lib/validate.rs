@@ -0,0 +1,244 @@
+use std::{fmt::Display, path::Path};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{config, variables::Variables, Artifact, ArtifactType};
+
+#[derive(Debug, Serialize)]
+/// A problem found in a configuration by `validate_path` or `validate_reader`
+pub struct Issue {
+    pub file: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub artifact: Option<String>,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}:{}: ", self.file, line, column)?,
+            (Some(line), None) => write!(f, "{}:{}: ", self.file, line)?,
+            _ => write!(f, "{}: ", self.file)?,
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Load the configuration file and the files it includes without running it, and return all the problems found:
+/// syntax errors, unknown fields, regexes that do not compile, maps referencing fields the artifact does not produce,
+/// missing modifier parameters and duplicated artifact names
+pub fn validate_path(config_path: &str) -> Vec<Issue> {
+    match std::fs::read(config_path) {
+        Ok(raw_config) => {
+            let base_dir = Path::new(config_path)
+                .parent()
+                .unwrap_or_else(|| Path::new("."));
+            config::validate(&raw_config, config_path, base_dir)
+        }
+        Err(e) => vec![Issue {
+            file: config_path.to_string(),
+            line: None,
+            column: None,
+            artifact: None,
+            message: format!(
+                "Can not read configuration file '{}', ERROR: {}",
+                config_path, e
+            ),
+        }],
+    }
+}
+
+/// Same as `validate_path` but it acceptes the configuration content, the `include` paths are relative to the current directory
+pub fn validate_reader(raw_config: &[u8], source: &str) -> Vec<Issue> {
+    config::validate(raw_config, source, Path::new("."))
+}
+
+/// Returns the line of the artifact definition in the configuration, `occurrence` is the number of the definitions
+/// of the same name before it in the configuration (ex. `1` for the first duplicate)
+pub(crate) fn artifact_line(raw_config: &[u8], name: &str, occurrence: usize) -> Option<usize> {
+    String::from_utf8_lossy(raw_config)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim_start().trim_start_matches('-').trim();
+            match line.strip_prefix("name:") {
+                Some(value) => value.trim().trim_matches(|c| c == '"' || c == '\'') == name,
+                None => false,
+            }
+        })
+        .nth(occurrence)
+        .map(|(index, _)| index + 1)
+}
+
+/// Checks the fields of a single artifact, returns the problems found
+pub(crate) fn check_artifact(artifact: &Artifact) -> Vec<String> {
+    let mut problems = vec![];
+
+    if artifact.artifacts.is_empty() {
+        problems.push(String::from(
+            "The artifact does not have any queries, paths or commands",
+        ));
+    }
+
+    let regex = match (&artifact.artifact_type, &artifact.regex) {
+        (ArtifactType::Parse | ArtifactType::Command, Some(regex)) => match Regex::new(regex) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                problems.push(format!(
+                    "The regex '{}' does not compile, ERROR: {}",
+                    regex, e
+                ));
+                None
+            }
+        },
+        (ArtifactType::Parse, None) => {
+            problems.push(String::from(
+                "The artifact type 'parse' requires the field 'regex'",
+            ));
+            None
+        }
+        (ArtifactType::Query | ArtifactType::Collection, Some(_)) => {
+            problems.push(format!(
+                "The field 'regex' is not used by the artifact type '{}'",
+                artifact.artifact_type
+            ));
+            None
+        }
+        _ => None,
+    };
+
+    if let ArtifactType::Collection | ArtifactType::Parse = artifact.artifact_type {
+        for path in artifact.artifacts.iter() {
+            if let Err(e) = glob::Pattern::new(path) {
+                problems.push(format!(
+                    "The path '{}' is not a valid glob pattern, ERROR: {}",
+                    path, e
+                ));
+            }
+            if let Err(e) = Variables::check(path) {
+                problems.push(format!(
+                    "Unable to expand the variables in the path '{}', ERROR: {}",
+                    path, e
+                ));
+            }
+        }
+    }
+
+    if artifact.hashes.is_some() && !matches!(artifact.artifact_type, ArtifactType::Collection) {
+        problems.push(format!(
+            "The field 'hashes' is not used by the artifact type '{}'",
+            artifact.artifact_type
+        ));
+    }
+
+    let maps = match &artifact.maps {
+        Some(maps) => maps,
+        None => return problems,
+    };
+
+    // The fields produced by the artifact, the fields of `query` artifacts are only known when the query runs
+    let fields: Option<Vec<String>> = match (&artifact.artifact_type, &regex) {
+        (ArtifactType::Collection, _) => {
+            problems.push(String::from(
+                "The field 'maps' is not supported by the artifact type 'collection'",
+            ));
+            return problems;
+        }
+        (ArtifactType::Query, _) => None,
+        (ArtifactType::Command, None) if artifact.regex.is_none() => Some(vec![
+            String::from("line"),
+            String::from("stdout"),
+            String::from("stderr"),
+        ]),
+        (ArtifactType::Command, Some(re)) => {
+            let mut fields: Vec<String> = re.capture_names().flatten().map(String::from).collect();
+            fields.push(String::from("line"));
+            fields.push(String::from("stderr"));
+            Some(fields)
+        }
+        (ArtifactType::Parse, Some(re)) => {
+            let mut fields: Vec<String> = re.capture_names().flatten().map(String::from).collect();
+            fields.push(String::from("full_path"));
+            if artifact.uses_user_variables() {
+                fields.push(String::from("user"));
+            }
+            Some(fields)
+        }
+        // The regex does not compile, the problem is already reported
+        _ => None,
+    };
+
+    for map in maps.iter() {
+        if let Some(fields) = &fields {
+            if !fields.contains(&map.from) {
+                problems.push(format!(
+                    "The map from '{}' references a field that is not produced by the artifact, the available fields are: [{}]",
+                    map.from,
+                    fields.join(", ")
+                ));
+            }
+        }
+        if let Some(modifier) = &map.modifier {
+            if let Err(e) = modifier.check() {
+                problems.push(format!("{} (map from '{}')", e, map.from));
+            }
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifact_line_finds_every_definition() {
+        let raw = b"artifacts:\n  - name: users\n    type: query\n  - name: \"processes\"\n    type: query\n  -   name: 'users'\n";
+        assert_eq!(artifact_line(raw, "users", 0), Some(2));
+        assert_eq!(artifact_line(raw, "users", 1), Some(6));
+        assert_eq!(artifact_line(raw, "users", 2), None);
+        assert_eq!(artifact_line(raw, "processes", 0), Some(4));
+        assert_eq!(artifact_line(raw, "user", 0), None);
+    }
+
+    #[test]
+    fn check_artifact_reports_problems() {
+        assert!(check_artifact(&Artifact::default()).is_empty());
+
+        let empty = Artifact {
+            artifacts: vec![],
+            ..Artifact::default()
+        };
+        assert_eq!(
+            check_artifact(&empty),
+            vec!["The artifact does not have any queries, paths or commands"]
+        );
+
+        let hashed = Artifact {
+            hashes: Some(vec![]),
+            regex: Some(String::from("(?P<name>.*)")),
+            ..Artifact::default()
+        };
+        assert_eq!(
+            check_artifact(&hashed),
+            vec![
+                "The field 'regex' is not used by the artifact type 'query'",
+                "The field 'hashes' is not used by the artifact type 'query'",
+            ]
+        );
+
+        let parse = Artifact {
+            artifact_type: ArtifactType::Parse,
+            artifacts: vec![String::from("/var/log/%users.password%")],
+            regex: Some(String::from("(?P<name")),
+            ..Artifact::default()
+        };
+        let problems = check_artifact(&parse);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("The regex '(?P<name' does not compile"));
+        assert!(problems[1].starts_with("Unable to expand the variables in the path"));
+    }
+}
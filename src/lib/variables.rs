@@ -0,0 +1,345 @@
+use std::{collections::BTreeMap, fs};
+
+use log::*;
+use osquery_rs::OSQuery;
+
+/// A local user used to expand the `%users.<field>%` variables
+#[derive(Debug, Clone)]
+pub struct User {
+    pub username: String,
+    pub uid: String,
+    pub gid: String,
+    pub homedir: String,
+    pub shell: String,
+}
+
+/// The fields of the users supported by the `%users.<field>%` variables
+const USER_FIELDS: [&str; 5] = ["homedir", "username", "uid", "gid", "shell"];
+
+impl User {
+    fn field(&self, name: &str) -> &str {
+        match name {
+            "homedir" => &self.homedir,
+            "username" => &self.username,
+            "uid" => &self.uid,
+            "gid" => &self.gid,
+            _ => &self.shell,
+        }
+    }
+}
+
+/// A path pattern after the variables expansion, `user` is set when the pattern was expanded for a user
+#[derive(Debug, PartialEq, Eq)]
+pub struct Expanded {
+    pub pattern: String,
+    pub user: Option<String>,
+}
+
+/// A part of a pattern parsed by `Variables::parse`
+enum Token<'p> {
+    Text(&'p str),
+    Hostname,
+    Env(&'p str),
+    User(&'p str),
+}
+
+/// Values of the variables supported in the paths of `collection` & `parse` artifacts:
+/// * `%hostname%`: the hostname of the machine
+/// * `%env.NAME%`: the value of the environment variable `NAME`
+/// * `%users.homedir%`, `%users.username%`, `%users.uid%`, `%users.gid%` & `%users.shell%`: the pattern is expanded once for every local user
+#[derive(Debug, Default)]
+pub struct Variables {
+    hostname: String,
+    users: Vec<User>,
+}
+
+impl Variables {
+    /// Load the local users from the osquery table `users` if osquery is available, otherwise from `/etc/passwd`
+    pub fn load(osquery_instance: Option<&OSQuery>) -> Self {
+        let hostname = match hostname::get() {
+            Ok(name) => name.to_string_lossy().to_string(),
+            Err(_) => String::from("HOSTNAME_NOT_FOUND"),
+        };
+        let users = match osquery_instance
+            .and_then(Self::users_from_osquery)
+            .filter(|users| !users.is_empty())
+        {
+            Some(users) => users,
+            None => Self::users_from_passwd("/etc/passwd"),
+        };
+        debug!("Loaded '{}' users for the variables expansion", users.len());
+        Self { hostname, users }
+    }
+
+    fn users_from_osquery(osquery_instance: &OSQuery) -> Option<Vec<User>> {
+        let sql = "select username, uid, gid, directory, shell from users";
+        let res = match osquery_instance.query(sql.to_string()) {
+            Ok(res) => res,
+            Err(e) => {
+                warn!(
+                    "Unable to list the users using osquery, using '/etc/passwd' instead, ERROR: {}",
+                    e
+                );
+                return None;
+            }
+        };
+        if res.status.and_then(|status| status.code) != Some(0) {
+            return None;
+        }
+        let field =
+            |row: &BTreeMap<String, String>, name: &str| row.get(name).cloned().unwrap_or_default();
+        Some(
+            res.response?
+                .iter()
+                .map(|row| User {
+                    username: field(row, "username"),
+                    uid: field(row, "uid"),
+                    gid: field(row, "gid"),
+                    homedir: field(row, "directory"),
+                    shell: field(row, "shell"),
+                })
+                .collect(),
+        )
+    }
+
+    fn users_from_passwd(path: &str) -> Vec<User> {
+        let passwd = match fs::read_to_string(path) {
+            Ok(passwd) => passwd,
+            Err(e) => {
+                error!("Unable to read the users from '{}', ERROR: '{}'", path, e);
+                return vec![];
+            }
+        };
+        passwd
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                match fields.len() {
+                    7 => Some(User {
+                        username: fields[0].to_string(),
+                        uid: fields[2].to_string(),
+                        gid: fields[3].to_string(),
+                        homedir: fields[5].to_string(),
+                        shell: fields[6].to_string(),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Checks if the pattern is expanded once for every user
+    pub fn uses_users(pattern: &str) -> bool {
+        pattern.contains("%users.")
+    }
+
+    /// Split the pattern into the text and the variables in a single left to right pass, the text is not escaped
+    fn parse(pattern: &str) -> Result<Vec<Token<'_>>, String> {
+        let mut tokens = vec![];
+        let mut rest = pattern;
+        loop {
+            let next = ["%hostname%", "%env.", "%users."]
+                .iter()
+                .filter_map(|prefix| rest.find(prefix).map(|start| (start, *prefix)))
+                .min();
+            let (start, prefix) = match next {
+                Some(next) => next,
+                None => {
+                    tokens.push(Token::Text(rest));
+                    return Ok(tokens);
+                }
+            };
+            tokens.push(Token::Text(&rest[..start]));
+            let after = &rest[start + prefix.len()..];
+            if prefix == "%hostname%" {
+                tokens.push(Token::Hostname);
+                rest = after;
+                continue;
+            }
+
+            let end = after
+                .find('%')
+                .ok_or_else(|| format!("the variable in '{}' is not closed", pattern))?;
+            let name = &after[..end];
+            match prefix {
+                "%env." if name.is_empty() => {
+                    return Err(format!(
+                        "the variable '%env.%' in '{}' has no environment variable name",
+                        pattern
+                    ))
+                }
+                "%env." => tokens.push(Token::Env(name)),
+                _ if USER_FIELDS.contains(&name) => tokens.push(Token::User(name)),
+                _ => return Err(format!(
+                    "unknown variable in '{}', the supported user variables are '%users.homedir%', '%users.username%', '%users.uid%', '%users.gid%' & '%users.shell%'",
+                    pattern
+                )),
+            }
+            rest = &after[end + 1..];
+        }
+    }
+
+    /// Checks the syntax of the variables in the pattern without expanding them
+    pub fn check(pattern: &str) -> Result<(), String> {
+        Self::parse(pattern).map(|_| ())
+    }
+
+    /// Replace the variables in the glob pattern, the values are escaped so they are matched literally and are not expanded again.
+    /// Patterns using `%users.<field>%` are expanded once for every user, users resulting in the same pattern are expanded once
+    pub fn expand(&self, pattern: &str) -> Result<Vec<Expanded>, String> {
+        let tokens = Self::parse(pattern)?;
+        // The text before every user variable, the hostname & the environment variables are the same for every user
+        let mut parts: Vec<(String, Option<&str>)> = vec![];
+        let mut text = String::new();
+        for token in tokens {
+            match token {
+                Token::Text(value) => text.push_str(value),
+                Token::Hostname => text.push_str(&glob::Pattern::escape(&self.hostname)),
+                Token::Env(name) => {
+                    let value = std::env::var(name)
+                        .map_err(|_| format!("the environment variable '{}' is not set", name))?;
+                    text.push_str(&glob::Pattern::escape(&value));
+                }
+                Token::User(field) => parts.push((std::mem::take(&mut text), Some(field))),
+            }
+        }
+        parts.push((text, None));
+
+        if parts.len() == 1 {
+            return Ok(vec![Expanded {
+                pattern: parts.remove(0).0,
+                user: None,
+            }]);
+        }
+
+        let mut expanded: Vec<Expanded> = vec![];
+        for user in self.users.iter() {
+            let pattern: String = parts
+                .iter()
+                .map(|(text, field)| match field {
+                    Some(field) => format!("{}{}", text, glob::Pattern::escape(user.field(field))),
+                    None => text.clone(),
+                })
+                .collect();
+            if user.homedir.is_empty() || expanded.iter().any(|e| e.pattern == pattern) {
+                continue;
+            }
+            expanded.push(Expanded {
+                pattern,
+                user: Some(user.username.clone()),
+            });
+        }
+        Ok(expanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str, homedir: &str) -> User {
+        User {
+            username: username.to_string(),
+            uid: String::from("1000"),
+            gid: String::from("1000"),
+            homedir: homedir.to_string(),
+            shell: String::from("/bin/sh"),
+        }
+    }
+
+    fn variables() -> Variables {
+        Variables {
+            hostname: String::from("host[1]"),
+            users: vec![
+                user("alice", "/home/alice"),
+                user("bob", "/home/bob"),
+                user("alias", "/home/alice"),
+                user("nobody", ""),
+            ],
+        }
+    }
+
+    fn patterns(expanded: Vec<Expanded>) -> Vec<String> {
+        expanded.into_iter().map(|e| e.pattern).collect()
+    }
+
+    #[test]
+    fn expand_hostname_is_escaped() {
+        let expanded = variables().expand("/var/log/%hostname%/*.log").unwrap();
+        assert_eq!(
+            expanded,
+            vec![Expanded {
+                pattern: String::from("/var/log/host[[]1[]]/*.log"),
+                user: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn expand_env_is_not_expanded_again() {
+        std::env::set_var("FENNEC_TEST_SELF", "%env.FENNEC_TEST_SELF%");
+        let expanded = variables().expand("/tmp/%env.FENNEC_TEST_SELF%").unwrap();
+        assert_eq!(
+            patterns(expanded),
+            vec![String::from("/tmp/%env.FENNEC_TEST_SELF%")]
+        );
+
+        std::env::set_var("FENNEC_TEST_GLOB", "a*b");
+        let expanded = variables().expand("%env.FENNEC_TEST_GLOB%/x").unwrap();
+        assert_eq!(patterns(expanded), vec![String::from("a[*]b/x")]);
+    }
+
+    #[test]
+    fn expand_unset_env_is_an_error() {
+        let err = variables()
+            .expand("/tmp/%env.FENNEC_TEST_UNSET%")
+            .unwrap_err();
+        assert!(err.contains("FENNEC_TEST_UNSET"));
+    }
+
+    #[test]
+    fn expand_users_once_per_pattern() {
+        let expanded = variables().expand("%users.homedir%/.bash_history").unwrap();
+        assert_eq!(
+            expanded,
+            vec![
+                Expanded {
+                    pattern: String::from("/home/alice/.bash_history"),
+                    user: Some(String::from("alice")),
+                },
+                Expanded {
+                    pattern: String::from("/home/bob/.bash_history"),
+                    user: Some(String::from("bob")),
+                },
+            ]
+        );
+
+        let expanded = variables()
+            .expand("/var/%users.username%/%users.uid%")
+            .unwrap();
+        assert_eq!(
+            patterns(expanded),
+            vec![
+                String::from("/var/alice/1000"),
+                String::from("/var/bob/1000"),
+                String::from("/var/alias/1000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_reports_invalid_variables() {
+        assert!(Variables::check("/home/%users.homedir%/%hostname%").is_ok());
+        assert!(Variables::check("/tmp/100%").is_ok());
+        assert!(Variables::check("/tmp/%env.HOME")
+            .unwrap_err()
+            .contains("is not closed"));
+        assert!(Variables::check("/tmp/%env.%")
+            .unwrap_err()
+            .contains("has no environment variable name"));
+        assert!(Variables::check("/tmp/%users.password%")
+            .unwrap_err()
+            .contains("unknown variable"));
+    }
+}
@@ -1,36 +1,47 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ErrorKind};
 use colored::*;
+use fennec::encryption::{parse_recipients, EncryptedSink, EncryptedWriter};
+use fennec::plan::PlanStatus;
+use fennec::schema;
+use fennec::sink::{DirectorySink, OutputSink, TarSink, VolumeIndex, VolumeSink, ZipSink};
+use fennec::throttle::{set_io_class, set_niceness, IoClass};
 use fennec::upload::UploadArtifacts;
-use fennec::{Fennec, OutputFormat};
+use fennec::validate::{validate_path, validate_reader, Issue};
+use fennec::{Fennec, OutputFormat, Profile};
 use log::*;
 use log4rs::{
     append::{
         console::{ConsoleAppender, Target},
         file::FileAppender,
+        Append,
     },
     config::{Appender, Config, Root},
-    encode::pattern::PatternEncoder,
+    encode::{pattern::PatternEncoder, writer::simple::SimpleWriter, Encode},
 };
+use nix::sys::statvfs::statvfs;
 use nix::unistd::Uid;
 use rust_embed::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::OpenOptions,
-    os::unix::fs::{OpenOptionsExt, PermissionsExt},
-};
-use std::{
+    collections::BTreeMap,
     fs::{self, File},
-    io::{BufRead, BufReader, Cursor, Read, Write},
+    io::{self, BufWriter, Cursor, Write},
+    path::Path,
     process::exit,
+    sync::{Arc, Mutex},
     time::Instant,
 };
-use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+use std::{
+    fs::OpenOptions,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
 
 #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
 #[derive(RustEmbed)]
 #[folder = "deps/linux/"]
 #[include = "fennec.yaml"]
 #[include = "x86_64/osqueryd"]
+#[include = "x86_64/extensions/*.ext"]
 #[prefix = ""]
 struct Asset;
 #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
@@ -38,6 +49,7 @@ struct Asset;
 #[folder = "deps/linux/"]
 #[include = "fennec.yaml"]
 #[include = "aarch64/osqueryd"]
+#[include = "aarch64/extensions/*.ext"]
 #[prefix = ""]
 struct Asset;
 #[cfg(target_os = "freebsd")]
@@ -51,7 +63,32 @@ struct Asset;
 #[prefix = ""]
 struct Asset;
 
-fn init_logger(log_path: &str, level: log::LevelFilter, quiet: bool) -> log4rs::Handle {
+/// Keeps the log in memory instead of the log file, used when the artifact package is written to stdout so nothing is written to the disk
+#[derive(Debug)]
+struct MemoryAppender {
+    encoder: PatternEncoder,
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Append for MemoryAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut buffer = match self.buffer.lock() {
+            Ok(buffer) => buffer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        self.encoder.encode(&mut SimpleWriter(&mut *buffer), record)
+    }
+
+    fn flush(&self) {}
+}
+
+/// Initialize the logger, the log is written to `log_path` or to `memory` if set
+fn init_logger(
+    log_path: &str,
+    level: log::LevelFilter,
+    quiet: bool,
+    memory: Option<Arc<Mutex<Vec<u8>>>>,
+) -> log4rs::Handle {
     let log_format = "{d(%Y-%m-%d %H:%M:%S)(utc)} [{t}:{L:<3}] {h({l:<5})} {m}\n";
 
     let stderr = ConsoleAppender::builder()
@@ -60,15 +97,23 @@ fn init_logger(log_path: &str, level: log::LevelFilter, quiet: bool) -> log4rs::
         .build();
 
     // Logging to log file.
-    let logfile = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_format)))
-        .build(log_path)
-        .unwrap();
+    let logfile: Box<dyn Append> = match memory {
+        Some(buffer) => Box::new(MemoryAppender {
+            encoder: PatternEncoder::new(log_format),
+            buffer,
+        }),
+        None => Box::new(
+            FileAppender::builder()
+                .encoder(Box::new(PatternEncoder::new(log_format)))
+                .build(log_path)
+                .unwrap(),
+        ),
+    };
 
     // Log Trace level output to file where trace is the default level
     // and the programmatically specified level to stderr
     let mut config_builder =
-        Config::builder().appender(Appender::builder().build("logfile", Box::new(logfile)));
+        Config::builder().appender(Appender::builder().build("logfile", logfile));
 
     if !quiet {
         config_builder =
@@ -85,12 +130,113 @@ fn init_logger(log_path: &str, level: log::LevelFilter, quiet: bool) -> log4rs::
     log4rs::init_config(config).unwrap()
 }
 
+/// Create the output sink for the output type, encrypted and `stdout` outputs can not seek so the ZIP file is written in streaming mode.
+/// The encrypted stream is finished by the `finish` of the returned sink
+fn open_sink(
+    output: &str,
+    output_type: &str,
+    recipients: &[age::x25519::Recipient],
+) -> Result<Box<dyn OutputSink>, io::Error> {
+    let package_sink = |writer: Box<dyn Write + Send>| -> Box<dyn OutputSink> {
+        match output_type {
+            "tar" => Box::new(TarSink::new(writer)),
+            "tar.gz" => Box::new(TarSink::gzip(writer)),
+            _ => Box::new(ZipSink::stream(writer)),
+        }
+    };
+    match (output, output_type, recipients.is_empty()) {
+        (_, "dir", _) => Ok(Box::new(DirectorySink::new(output)?)),
+        (path, "zip", true) if path != "-" => Ok(Box::new(ZipSink::new(File::create(output)?))),
+        (_, _, true) => Ok(package_sink(open_output(output)?)),
+        (_, _, false) => {
+            let writer = EncryptedWriter::new(open_output(output)?, recipients)?;
+            Ok(Box::new(EncryptedSink::new(
+                package_sink(Box::new(writer.clone())),
+                writer,
+            )))
+        }
+    }
+}
+
+/// Parse a size in bytes with an optional unit suffix `K`, `M`, `G` or `T` (ex. `2G`)
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => size.split_at(index),
+        None => (size, ""),
+    };
+    let multiplier: u64 = match unit
+        .to_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown size unit '{}'", unit)),
+    };
+    number
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size '{}', ERROR: {}", size, e))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("the size '{}' is too large", size))
+}
+
+/// Returns the free space in bytes available to unprivileged users on the filesystem of the output
+fn available_space(output: &str) -> Result<u64, nix::Error> {
+    // The output does not exist yet, use the closest existing parent directory
+    let mut path = Path::new(output);
+    while !path.exists() {
+        path = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+    }
+    let stats = statvfs(path)?;
+    Ok(stats.blocks_available() as u64 * stats.fragment_size() as u64)
+}
+
+/// Load the configuration file or the embedded configuration, the configuration is loaded to plan the collection then to run it
+fn load_fennec<'a>(
+    config_path: Option<&str>,
+    embedded_config: &[u8],
+    overrides: &[String],
+    output: &'a mut dyn OutputSink,
+) -> Fennec<'a> {
+    let fennec = match config_path {
+        Some(config_path) => Fennec::from_path(config_path, output),
+        None => Fennec::from_reader(embedded_config, output),
+    }
+    .and_then(|mut fennec| {
+        fennec.apply_overrides(overrides)?;
+        Ok(fennec)
+    });
+    match fennec {
+        Ok(fennec) => fennec,
+        Err(e) => {
+            error!("{}", e.message);
+            exit(1);
+        }
+    }
+}
+
+/// Open the output file or `stdout` when the output is `-`
+fn open_output(output: &str) -> Result<Box<dyn Write + Send>, io::Error> {
+    match output {
+        "-" => Ok(Box::new(BufWriter::new(io::stdout()))),
+        _ => Ok(Box::new(File::create(output)?)),
+    }
+}
+
 macro_rules! init_args {
     ($default_output_name:expr,$default_log_path:expr, $osquery_embedded:expr, $config_embedded:expr) => {
         App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author("AbdulRhman Alfaifi <aalfaifi@u0041.co>")
         .about("Aritfact collection tool for *nix systems")
+        .args_override_self(true)
         .arg(
             Arg::new("config")
                 .short('c')
@@ -110,6 +256,115 @@ macro_rules! init_args {
                 .default_value("60")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .display_order(7)
+                .value_name("N")
+                .help("Sets the number of artifacts to process in parallel")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("artifacts")
+                .long("artifacts")
+                .value_name("NAME")
+                .help("Only collect the artifacts with these names (supports glob, comma separated)")
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true),
+        )
+        .arg(
+            Arg::new("exclude_artifacts")
+                .long("exclude-artifacts")
+                .value_name("NAME")
+                .help("Skip the artifacts with these names (supports glob, comma separated)")
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true),
+        )
+        .arg(
+            Arg::new("tags")
+                .long("tags")
+                .value_name("TAG")
+                .help("Only collect the artifacts tagged with any of these tags (comma separated)")
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true),
+        )
+        .arg(
+            Arg::new("set")
+                .long("set")
+                .value_name("KEY=VALUE")
+                .help("Override a field of the artifacts in the configuration, can be used multiple times (ex. 'artifact.<name>.timeout=30', 'artifact.<name>.paths+=/opt/app/logs/*')")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("max_package_size")
+                .long("max-package-size")
+                .value_name("SIZE")
+                .help("Skips the collected files that do not fit once the artifact package reaches this size before compression, units K, M, G and T are supported (ex. '1G')")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_volume_size")
+                .long("max-volume-size")
+                .value_name("SIZE")
+                .help("Split the artifact package into numbered volumes (ex. 'host.001.zip') of about this size with an index 'host.index.json', units K, M, G and T are supported (ex. '2G'). The volumes are split between entries, a file larger than the size is written to its own volume that exceeds the size")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("nice")
+                .long("nice")
+                .value_name("N")
+                .help("Sets the CPU scheduling priority (niceness) from 0 to 19 (lowest priority), inherited by osquery and the commands")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("io_class")
+                .long("io-class")
+                .value_name("CLASS")
+                .help("Sets the IO scheduling class (Linux only), inherited by osquery and the commands. 'idle' uses the disk only when no other process needs it")
+                .takes_value(true)
+                .possible_values(["idle", "best-effort"]),
+        )
+        .arg(
+            Arg::new("max_read_rate")
+                .long("max-read-rate")
+                .value_name("SIZE")
+                .help("Limits the reads of collected and parsed files to this size per second for all the workers, units K, M, G and T are supported (ex. '10M')")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_write_rate")
+                .long("max-write-rate")
+                .value_name("SIZE")
+                .help("Limits the writes to the artifact package to this size per second before compression, units K, M, G and T are supported (ex. '10M')")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("recipient")
+                .long("recipient")
+                .value_name("PUBLIC_KEY")
+                .help("Encrypt the artifact package using age for these X25519 public keys 'age1...' (comma separated), only the holders of the private keys can decrypt it. Set it in the embedded configuration 'args' to encrypt every package")
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Use the arguments and the artifacts of this profile from the embedded configuration, the command line arguments override them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .help("Print what the configuration would collect without running any artifact then exit"),
+        )
         .arg(
             Arg::new("show_config")
                 .long("show-config")
@@ -120,6 +375,11 @@ macro_rules! init_args {
                 .long("show-embedded")
                 .help("Show the embedded files metadata"),
         )
+        .arg(
+            Arg::new("print_schema")
+                .long("print-schema")
+                .help("Print the JSON schema of the configuration file then exit"),
+        )
         .arg(
             Arg::new("log_path")
                 .short('f')
@@ -160,6 +420,15 @@ macro_rules! init_args {
                 .possible_values(["jsonl", "csv", "kjson"])
                 .default_value("jsonl"),
         )
+        .arg(
+            Arg::new("output_type")
+                .long("output-type")
+                .value_name("TYPE")
+                .help("Sets the artifact package type, 'dir' writes the results to a directory tree. When the output file name is not set the extension of the default name follows the type")
+                .takes_value(true)
+                .possible_values(["zip", "tar", "tar.gz", "dir"])
+                .default_value("zip"),
+        )
         .arg(
             Arg::new("osquery_path")
                 .long("osquery-path")
@@ -188,6 +457,43 @@ macro_rules! init_args {
                 .takes_value(true)
                 .multiple_values(true)
         )
+        .arg(
+            Arg::new("osquery_socket")
+                .long("osquery-socket")
+                .value_name("PATH")
+                .help("Run the queries using the extension socket of a running osquery daemon (ex. '/var/osquery/osquery.em'), osquery is spawned if the socket is not available")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("osquery_retries")
+                .long("osquery-retries")
+                .value_name("N")
+                .help("Sets the number of times an osquery query is retried when osquery crashes or stops answering, the osquery spawned by Fennec is restarted before retrying and a query that timed out is retried with twice the timeout")
+                .default_value("2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("osquery_flag")
+                .long("osquery-flag")
+                .value_name("FLAG")
+                .help("Add a flag to the spawned osquery, can be used multiple times (ex. '--osquery-flag=--disable_events', '--osquery-flag=--disable_watchdog=false --osquery-flag=--watchdog_memory_limit=1000'). The flags are added after the flags of the configuration section 'osquery'")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("osquery_extension")
+                .long("osquery-extension")
+                .value_name("PATH")
+                .help("Load the osquery extension (ex. 'yara.ext') in the spawned osquery, can be used multiple times. The extensions embedded into Fennec are always loaded")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("string_results")
+                .long("string-results")
+                .help("Output all the osquery results as strings instead of using the types of the table columns (legacy behaviour)")
+        )
         .arg(
             Arg::new("non-root")
                 .long("non-root")
@@ -200,12 +506,23 @@ macro_rules! init_args {
                 .display_order(5)
                 .help("Do not print logs to stdout"),
         )
+        .subcommand(
+            App::new("validate")
+                .about("Check the configuration files and the files they include without running them, the problems found are printed and the exit code is 1 if any. The '-c' configuration or the embedded configuration is checked if no files are specified")
+                .arg(
+                    Arg::new("configs")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .multiple_values(true),
+                ),
+        )
     };
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmbeddedConfig {
     args: Option<Vec<String>>,
+    profiles: Option<BTreeMap<String, Profile>>,
 }
 
 fn main() {
@@ -229,18 +546,12 @@ fn main() {
         None => String::new(),
     };
 
-    let osquery_embedded = match Asset::get(&osquery_asset_name) {
-        Some(_) => true,
-        None => false,
-    };
+    let osquery_embedded = Asset::get(&osquery_asset_name).is_some();
 
-    let config_embedded = match Asset::get(&config_asset_name) {
-        Some(_) => true,
-        None => false,
-    };
+    let config_embedded = Asset::get(&config_asset_name).is_some();
 
     let default_output_name = match hostname::get() {
-        Ok(name) => format!("{}.zip", name.to_string_lossy().to_string()),
+        Ok(name) => format!("{}.zip", name.to_string_lossy()),
         Err(_) => {
             let hostname = match option_env!("HOSTNAME") {
                 Some(name) => name.to_string(),
@@ -265,62 +576,98 @@ fn main() {
         None => None,
     };
 
-    let conf_matches = match &embedded_config {
-        Some(embedded_config) => {
-            let reader = Cursor::new(embedded_config.clone());
-            let embedded_conf_args: EmbeddedConfig =
-                serde_yaml::from_reader(reader.clone()).unwrap();
-            match embedded_conf_args.args {
-                Some(mut conf_args) => {
-                    let mut args = vec![String::from("")];
-                    args.append(&mut conf_args);
-                    init_args!(
-                        &default_output_name,
-                        &default_log_path,
-                        osquery_embedded,
-                        config_embedded
-                    )
-                    .get_matches_from(args)
-                }
-                None => {
-                    let empty: Vec<String> = vec![];
-                    init_args!(
-                        &default_output_name,
-                        &default_log_path,
-                        osquery_embedded,
-                        config_embedded
-                    )
-                    .get_matches_from(empty)
-                }
+    if let Some(validate_matches) = cli_matches.subcommand_matches("validate") {
+        let issues: Vec<Issue> = match (
+            validate_matches.values_of("configs"),
+            cli_matches.value_of("config"),
+            &embedded_config,
+        ) {
+            (Some(paths), _, _) => paths.flat_map(validate_path).collect(),
+            (None, Some(config_path), _) => validate_path(config_path),
+            (None, None, Some(embedded_config)) => validate_reader(embedded_config, "<embedded>"),
+            (None, None, None) => {
+                eprintln!("No embedded configuration and no configuration files were specified, specify the files to check (ex. 'fennec validate fennec.yaml')");
+                exit(1);
+            }
+        };
+        for issue in issues.iter() {
+            println!("{}", issue);
+        }
+        match issues.len() {
+            0 => eprintln!("{}", "No problems found".green().bold()),
+            n => {
+                eprintln!("{}", format!("Found '{}' problem(s)", n).red().bold());
+                exit(1);
             }
         }
-        None => {
-            let empty: Vec<String> = vec![];
-            init_args!(
+        exit(0);
+    }
+
+    let embedded_conf: EmbeddedConfig = match &embedded_config {
+        Some(embedded_config) => serde_yaml::from_slice(embedded_config).unwrap(),
+        None => EmbeddedConfig {
+            args: None,
+            profiles: None,
+        },
+    };
+    let mut conf_args = vec![String::from("")];
+    conf_args.extend(embedded_conf.args.clone().unwrap_or_default());
+    let mut conf_matches = init_args!(
+        &default_output_name,
+        &default_log_path,
+        osquery_embedded,
+        config_embedded
+    )
+    .get_matches_from(&conf_args);
+
+    // The profile can be selected in the command line arguments or in the embedded configuration arguments
+    let profile = match cli_matches.occurrences_of("profile") {
+        0 => conf_matches.value_of("profile"),
+        _ => cli_matches.value_of("profile"),
+    }
+    .map(|name| name.to_string());
+    if let Some(name) = &profile {
+        let profiles = embedded_conf.profiles.unwrap_or_default();
+        let selected = match profiles.get(name) {
+            Some(selected) => selected,
+            None => init_args!(
                 &default_output_name,
                 &default_log_path,
                 osquery_embedded,
                 config_embedded
             )
-            .get_matches_from(empty)
+            .error(
+                ErrorKind::InvalidValue,
+                format!(
+                    "The profile '{}' is not defined in the embedded configuration, the available profiles are: [{}]",
+                    name,
+                    profiles.keys().cloned().collect::<Vec<String>>().join(", ")
+                ),
+            )
+            .exit(),
+        };
+        // The profile arguments override the embedded configuration arguments
+        conf_args.extend(selected.args.clone().unwrap_or_default());
+        if let Some(artifacts) = &selected.artifacts {
+            conf_args.push(String::from("--artifacts"));
+            conf_args.push(artifacts.join(","));
         }
-    };
+        conf_matches = init_args!(
+            &default_output_name,
+            &default_log_path,
+            osquery_embedded,
+            config_embedded
+        )
+        .get_matches_from(&conf_args);
+    }
 
-    let non_root = match cli_matches.occurrences_of("non-root") {
-        0 => match conf_matches.occurrences_of("non-root") {
-            0 => false,
-            _ => true,
-        },
-        _ => true,
-    };
+    let non_root =
+        cli_matches.occurrences_of("non-root") > 0 || conf_matches.occurrences_of("non-root") > 0;
 
-    let quiet = match cli_matches.occurrences_of("quiet") {
-        0 => match conf_matches.occurrences_of("quiet") {
-            0 => false,
-            _ => true,
-        },
-        _ => true,
-    };
+    let quiet = cli_matches.occurrences_of("quiet") > 0 || conf_matches.occurrences_of("quiet") > 0;
+
+    let string_results = cli_matches.occurrences_of("string_results") > 0
+        || conf_matches.occurrences_of("string_results") > 0;
 
     let log_path = match cli_matches.occurrences_of("log_path") {
         0 => conf_matches.value_of("log_path").unwrap(),
@@ -339,7 +686,38 @@ fn main() {
         }
     };
 
-    to_cleanup.push(log_path.to_string());
+    let jobs = match cli_matches.occurrences_of("jobs") {
+        0 => conf_matches.value_of("jobs").unwrap().parse::<usize>(),
+        _ => cli_matches.value_of("jobs").unwrap().parse::<usize>(),
+    };
+
+    let jobs = match jobs {
+        Ok(j) => j,
+        Err(e) => {
+            panic!("The 'jobs' option should be a number, ERROR: {}", e);
+        }
+    };
+
+    let osquery_retries = match cli_matches.occurrences_of("osquery_retries") {
+        0 => conf_matches
+            .value_of("osquery_retries")
+            .unwrap()
+            .parse::<u32>(),
+        _ => cli_matches
+            .value_of("osquery_retries")
+            .unwrap()
+            .parse::<u32>(),
+    };
+
+    let osquery_retries = match osquery_retries {
+        Ok(r) => r,
+        Err(e) => {
+            panic!(
+                "The 'osquery_retries' option should be a number, ERROR: {}",
+                e
+            );
+        }
+    };
 
     let log_level = {
         let level = match cli_matches.occurrences_of("log_level") {
@@ -361,7 +739,54 @@ fn main() {
         _ => cli_matches.values_of("upload_artifact"),
     };
 
-    init_logger(log_path, log_level, quiet);
+    let artifacts_filter = |name: &str| -> Vec<String> {
+        let values = match cli_matches.occurrences_of(name) {
+            0 => conf_matches.values_of(name),
+            _ => cli_matches.values_of(name),
+        };
+        match values {
+            Some(values) => values.map(|v| v.to_string()).collect(),
+            None => vec![],
+        }
+    };
+
+    let include_artifacts = artifacts_filter("artifacts");
+    let exclude_artifacts = artifacts_filter("exclude_artifacts");
+    let tags = artifacts_filter("tags");
+    let recipients = artifacts_filter("recipient");
+    let overrides = artifacts_filter("set");
+
+    let output_type = match cli_matches.occurrences_of("output_type") {
+        0 => conf_matches.value_of("output_type").unwrap(),
+        _ => cli_matches.value_of("output_type").unwrap(),
+    };
+
+    let output = match cli_matches.occurrences_of("output") {
+        0 => conf_matches.value_of("output").unwrap(),
+        _ => cli_matches.value_of("output").unwrap(),
+    };
+    let default_output = output == default_output_name;
+    let output = &match (default_output, output_type) {
+        (true, "tar") | (true, "tar.gz") => {
+            format!("{}.{}", output.trim_end_matches(".zip"), output_type)
+        }
+        (true, "dir") => output.trim_end_matches(".zip").to_string(),
+        _ => output.to_string(),
+    };
+    let output = &match default_output && !recipients.is_empty() {
+        true => format!("{}.age", output),
+        false => output.to_string(),
+    };
+
+    // The log is kept in memory when the package is written to stdout, then added to the package
+    let log_buffer = match output.as_str() {
+        "-" => Some(Arc::new(Mutex::new(vec![]))),
+        _ => {
+            to_cleanup.push(log_path.to_string());
+            None
+        }
+    };
+    init_logger(log_path, log_level, quiet, log_buffer.clone());
 
     if cli_matches.occurrences_of("show_config") >= 1 {
         match embedded_config {
@@ -386,9 +811,61 @@ fn main() {
         exit(0);
     }
 
-    if !Uid::effective().is_root() {
-        if !non_root {
-            error!("Running as non-root user, rerun Fennec as root or use the argument '--non-root' to run with normal user permissions (not recommended)");
+    if cli_matches.occurrences_of("print_schema") >= 1 {
+        match serde_json::to_string_pretty(&schema::schema()) {
+            Ok(schema) => println!("{}", schema),
+            Err(e) => {
+                error!(
+                    "Unable to serialize the configuration schema, ERROR: '{}'",
+                    e
+                );
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    if !Uid::effective().is_root() && !non_root {
+        error!("Running as non-root user, rerun Fennec as root or use the argument '--non-root' to run with normal user permissions (not recommended)");
+        exit(1);
+    }
+
+    let max_volume_size = match cli_matches.occurrences_of("max_volume_size") {
+        0 => conf_matches.value_of("max_volume_size"),
+        _ => cli_matches.value_of("max_volume_size"),
+    };
+    let max_volume_size = match max_volume_size.map(parse_size) {
+        Some(Ok(0)) | Some(Err(_)) => {
+            error!("The 'max-volume-size' option should be a size greater than 0 (ex. '2G')");
+            exit(1);
+        }
+        Some(Ok(size)) => Some(size),
+        None => None,
+    };
+    if max_volume_size.is_some() && (output == "-" || output_type == "dir") {
+        error!("The option 'max-volume-size' is not supported when the output is stdout or the output type is 'dir'");
+        exit(1);
+    }
+
+    let recipients = match parse_recipients(&recipients) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            error!("{}", e.message);
+            exit(1);
+        }
+    };
+    if !recipients.is_empty() && output_type == "dir" {
+        error!("The output type 'dir' can not be encrypted, use the output type 'zip', 'tar' or 'tar.gz'");
+        exit(1);
+    }
+
+    if output == "-" {
+        if output_type == "dir" {
+            error!("The output type 'dir' can not be written to stdout, use the output type 'zip', 'tar' or 'tar.gz'");
+            exit(1);
+        }
+        if nix::unistd::isatty(1).unwrap_or(false) {
+            error!("Refusing to write the artifact package to a terminal, redirect stdout to a file or a pipe (ex. 'fennec -o - > package.zip')");
             exit(1);
         }
     }
@@ -408,7 +885,11 @@ fn main() {
             env!("CARGO_PKG_VERSION").green().bold()
         );
 
-        println!("{}", ascii_art);
+        // The package is written to stdout when the output is '-'
+        match output == "-" {
+            true => eprintln!("{}", ascii_art),
+            false => println!("{}", ascii_art),
+        }
     }
 
     if !Uid::effective().is_root() {
@@ -417,34 +898,12 @@ fn main() {
 
     info!("Started Fennec {}", env!("CARGO_PKG_VERSION"));
 
-    let config = match cli_matches.value_of("config") {
-        Some(config_path) => {
-            let config_file = match File::open(config_path) {
-                Ok(f) => f,
-                Err(e) => panic!(
-                    "Error reading configuration file '{}', ERROR: '{}'",
-                    config_path, e
-                ),
-            };
-
-            Box::new(config_file) as Box<dyn Read>
-        }
-        None => match embedded_config {
-            Some(embedded_config) => {
-                let reader = Cursor::new(embedded_config);
-                Box::new(reader) as Box<dyn Read>
-            }
-            None => {
-                error!("No embedded configuration and configuration path was not specified, recompile with configuration or specify configuration path with commandline argument '-c'");
-                exit(1);
-            }
-        },
-    };
-
-    let output = match cli_matches.occurrences_of("output") {
-        0 => conf_matches.value_of("output").unwrap(),
-        _ => cli_matches.value_of("output").unwrap(),
-    };
+    let config_path = cli_matches.value_of("config");
+    if config_path.is_none() && embedded_config.is_none() {
+        error!("No embedded configuration and configuration path was not specified, recompile with configuration or specify configuration path with commandline argument '-c'");
+        exit(1);
+    }
+    let embedded_config = embedded_config.as_deref().unwrap_or_default();
 
     let osquery_path = match cli_matches.occurrences_of("osquery_path") {
         0 => conf_matches.value_of("osquery_path").unwrap(),
@@ -465,12 +924,104 @@ fn main() {
         }
     };
 
-    match Asset::get(&osquery_asset_name) {
-        Some(embedded_osquery) => {
+    let option = |name: &str| match cli_matches.occurrences_of(name) {
+        0 => conf_matches.value_of(name),
+        _ => cli_matches.value_of(name),
+    };
+
+    // Set before spawning any thread or process so they inherit the priorities
+    if let Some(niceness) = option("nice") {
+        let res = match niceness.parse::<i32>() {
+            Ok(niceness) => set_niceness(niceness),
+            Err(e) => {
+                error!("The 'nice' option should be a number, ERROR: {}", e);
+                exit(1);
+            }
+        };
+        if let Err(e) = res {
+            error!("{}", e.message);
+            exit(1);
+        }
+    }
+    if let Some(class) = option("io_class") {
+        let class = match class {
+            "idle" => IoClass::Idle,
+            _ => IoClass::BestEffort,
+        };
+        if let Err(e) = set_io_class(class) {
+            error!("{}", e.message);
+            exit(1);
+        }
+    }
+    let size_option = |name: &str| match option(name).map(parse_size) {
+        Some(Ok(0)) | Some(Err(_)) => {
+            error!(
+                "The '{}' option should be a size greater than 0 (ex. '10M')",
+                name.replace('_', "-")
+            );
+            exit(1);
+        }
+        Some(Ok(size)) => Some(size),
+        None => None,
+    };
+    let max_read_rate = size_option("max_read_rate");
+    let max_write_rate = size_option("max_write_rate");
+    let max_package_size = size_option("max_package_size");
+
+    if cli_matches.occurrences_of("dry_run") >= 1 {
+        let plan = {
+            // Nothing is written while planning, the output is discarded
+            let mut discard = ZipSink::new(Cursor::new(vec![]));
+            let fennec = load_fennec(config_path, embedded_config, &overrides, &mut discard)
+                .set_artifacts(include_artifacts.clone())
+                .set_excluded_artifacts(exclude_artifacts.clone())
+                .set_tags(tags.clone());
+            fennec.plan()
+        };
+        println!("{}", serde_json::to_string_pretty(&plan).unwrap());
+        info!(
+            "Dry run finished, '{}' artifacts would run with an estimated package size of '{}' bytes before compression",
+            plan.artifacts
+                .iter()
+                .filter(|a| a.status == PlanStatus::Run)
+                .count(),
+            plan.estimated_size
+        );
+        for path in to_cleanup {
+            if let Err(e) = fs::remove_file(&path) {
+                error!(
+                    "Unable to remove the files '{}', Please remove manually, ERROR: '{}'",
+                    path, e
+                );
+            }
+        }
+        exit(0);
+    }
+
+    // The embedded osquery is not written when the queries are sent to the running osquery daemon, the daemon should answer a query
+    // otherwise osquery is spawned
+    let osquery_socket = option("osquery_socket").filter(|socket| match Fennec::ping_osquery(socket, timeout) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!(
+                "The osquery daemon at '{}' did not answer a query, spawning osquery instead, ERROR: '{}'",
+                socket, e
+            );
+            false
+        }
+    });
+
+    match (osquery_socket, Asset::get(&osquery_asset_name)) {
+        (Some(socket), _) => info!(
+            "Using the osquery extension socket '{}' of the running osquery daemon",
+            socket
+        ),
+        (None, Some(embedded_osquery)) => {
             match OpenOptions::new()
                 .mode(0o700)
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .open(osquery_path)
             {
                 Ok(mut file) => {
@@ -511,7 +1062,7 @@ fn main() {
                 }
             };
         }
-        None => {
+        (None, None) => {
             warn!(
                 "No osquery embedded, using osquery binary at '{}'",
                 osquery_path
@@ -519,36 +1070,111 @@ fn main() {
         }
     }
 
-    let mut zipfile = match File::create(output) {
-        Ok(file) => ZipWriter::new(file),
+    // The embedded extensions are written next to osquery and loaded with the extensions of the command line
+    let mut osquery_extensions = artifacts_filter("osquery_extension");
+    if osquery_socket.is_none() {
+        for asset_name in Asset::iter().filter(|asset_name| asset_name.contains("extensions/")) {
+            let embedded_extension = Asset::get(&asset_name).unwrap();
+            let file_name = Path::new(asset_name.as_ref())
+                .file_name()
+                .unwrap_or_default();
+            let extension_path = Path::new(osquery_path).with_file_name(file_name);
+            let res = OpenOptions::new()
+                .mode(0o700)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&extension_path)
+                .and_then(|mut file| file.write_all(embedded_extension.data.as_ref()))
+                .and_then(|_| fs::canonicalize(&extension_path));
+            match res {
+                Ok(extension_path) => {
+                    let extension_path = extension_path.to_string_lossy().to_string();
+                    info!(
+                        "Successfuly wrote the embedded osquery extension '{}' to '{}'",
+                        asset_name, extension_path
+                    );
+                    to_cleanup.push(extension_path.clone());
+                    osquery_extensions.push(extension_path);
+                }
+                Err(e) => error!(
+                    "Unable to dump the embedded osquery extension '{}' to '{}', ERROR: '{}'",
+                    asset_name,
+                    extension_path.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    let sink = match max_volume_size {
+        Some(max_size) => {
+            let output_type = output_type.to_string();
+            let recipients = recipients.clone();
+            Ok(Box::new(VolumeSink::new(
+                output,
+                max_size,
+                Box::new(move |path: &Path| {
+                    open_sink(&path.to_string_lossy(), &output_type, &recipients)
+                }),
+            )) as Box<dyn OutputSink>)
+        }
+        None => open_sink(output, output_type, &recipients),
+    };
+    let mut sink = match sink {
+        Ok(sink) => sink,
         Err(e) => {
-            error!("Unable to create output file '{}', ERROR: '{}'", output, e);
+            error!("Unable to create output '{}', ERROR: '{}'", output, e);
             exit(1);
         }
     };
 
-    let foptions = FileOptions::default()
-        .large_file(true)
-        .compression_method(CompressionMethod::Deflated);
-
-    let mut fennec = Fennec::from_reader(config, &mut zipfile)
-        .unwrap()
+    let mut fennec = load_fennec(config_path, embedded_config, &overrides, &mut *sink)
         .set_output_format(output_format)
         .set_osquery_binary_path(osquery_path)
         .set_timeout(timeout)
-        .set_options(&foptions);
+        .set_osquery_retries(osquery_retries)
+        .set_osquery_flags(artifacts_filter("osquery_flag"))
+        .set_osquery_extensions(osquery_extensions)
+        .set_parallelism(jobs)
+        .set_artifacts(include_artifacts)
+        .set_excluded_artifacts(exclude_artifacts)
+        .set_tags(tags)
+        .set_typed_results(!string_results);
+    if let Some(socket) = osquery_socket {
+        fennec = fennec.set_osquery_socket(socket);
+    }
+    if let Some(rate) = max_read_rate {
+        fennec = fennec.set_read_rate(rate);
+    }
+    if let Some(rate) = max_write_rate {
+        fennec = fennec.set_write_rate(rate);
+    }
+    if let Some(max_size) = max_package_size {
+        fennec = fennec.set_max_package_size(max_size);
+    }
+    if output != "-" {
+        // Triage checks that the files to collect fit in the filesystem of the output before writing anything
+        match available_space(output) {
+            Ok(available) => fennec = fennec.set_available_space(available),
+            Err(e) => warn!(
+                "Unable to check the free space for the output '{}', ERROR: '{}'",
+                output, e
+            ),
+        }
+    }
 
     let zip_path = output.to_owned();
     let files_to_cleanup = to_cleanup.clone().to_owned();
 
     match ctrlc::set_handler(move || {
         warn!(
-            "Fennec interupted. Running cleanup, the ZIP file '{}' didn't close successfully",
+            "Fennec interupted. Running cleanup, the output '{}' didn't close successfully",
             zip_path
         );
 
         for path in &files_to_cleanup {
-            match fs::remove_file(&path) {
+            match fs::remove_file(path) {
                 Ok(_) => {
                     info!("Successfuly deleted the file '{}'", path);
                 }
@@ -570,72 +1196,145 @@ fn main() {
         }
     }
 
-    match fennec.triage() {
-        Ok(_) => {
+    let collected = match fennec.triage() {
+        Ok(report) => {
             let duration = time_took.elapsed();
             info!(
                 "Successfully finished collecting artifacts!, took '{}' seconds",
                 duration.as_secs_f64()
             );
+            if !report.is_complete() {
+                warn!(
+                    "'{}' artifact(s) failed and '{}' artifact(s) finished partially, check 'triage_report.json' for the errors",
+                    report.failed, report.partial
+                );
+            }
+            if report.truncated() > 0 {
+                warn!(
+                    "'{}' artifact(s) were truncated because the package size budget was reached",
+                    report.truncated()
+                );
+            }
 
             info!(
-                "Adding '{}' to compressed file '{}' and running cleanup.",
+                "Adding '{}' to the output '{}' and running cleanup.",
                 log_path, output
             );
 
-            match zipfile.start_file(log_path, foptions) {
-                Ok(_) => match File::open(log_path) {
-                    Ok(in_file) => {
-                        let mut reader = BufReader::with_capacity(1024 * 128, in_file);
-
-                        loop {
-                            let buf = reader.fill_buf().unwrap();
-                            if buf.len() == 0 {
-                                break;
-                            }
-                            let bytes = zipfile.write(&buf).unwrap();
-
-                            reader.consume(bytes);
-                        }
-                        zipfile.flush().unwrap();
-                        zipfile.finish().unwrap();
-                    }
-                    Err(e) => {
-                        error!("Unable to open the log file '{}', ERROR: '{}'", log_path, e);
-                    }
-                },
+            let added = match &log_buffer {
+                Some(buffer) => {
+                    // Copied so the logs written while adding it do not wait for the buffer
+                    let log = match buffer.lock() {
+                        Ok(log) => log.clone(),
+                        Err(poisoned) => poisoned.into_inner().clone(),
+                    };
+                    fennec.add_data(log_path, &log)
+                }
+                None => fennec.add_file(log_path, log_path),
+            };
+            if let Err(e) = added {
+                error!(
+                    "Unable to write log file to '{}', ERROR: '{}'",
+                    log_path, e.message
+                );
+            }
+            if let Err(e) = fennec.write_manifest() {
+                error!("Unable to write the manifest, ERROR: '{}'", e.message);
+            }
+            drop(fennec);
+            // Also finishes the encrypted stream, the package can not be read if it is not finished
+            match sink.finish() {
+                Ok(_) => true,
                 Err(e) => {
-                    error!("Unable to write log file to '{}', ERROR: '{}'", log_path, e);
+                    error!(
+                        "Unable to finish writing the output '{}', ERROR: '{}'",
+                        output, e
+                    );
+                    false
                 }
             }
         }
         Err(e) => {
             error!("Unable to collect triage image, ERROR: '{}'", e.message);
+            drop(fennec);
+            drop(sink);
+            // Nothing was written to the output
+            let removed = match (output.as_str(), output_type) {
+                ("-", _) => Ok(()),
+                (_, "dir") => fs::remove_dir(output),
+                // The volumes are created with their first entry
+                _ if max_volume_size.is_some() => Ok(()),
+                _ => fs::remove_file(output),
+            };
+            if let Err(e) = removed {
+                error!("Unable to remove the output '{}', ERROR: '{}'", output, e);
+            }
+            false
         }
     };
 
-    match upload {
-        Some(config) => {
-            for value in config {
-                match UploadArtifacts::new(value) {
-                    Ok(upload_artifact) => match upload_artifact.upload(output) {
-                        Ok(_) => {
-                            info!("Successfully uploaded the artifact package '{}'", output)
-                        }
-                        Err(e) => {
-                            error!(
-                                "Unable to upload the artifact package '{}', ERROR: {:?}",
-                                output, e
-                            );
-                        }
-                    },
+    if let Some(config) = upload.filter(|_| collected) {
+        let uploadable = match (output.as_str(), output_type) {
+            ("-", _) => {
+                error!(
+                    "Uploading the artifact package is not supported when it is written to stdout"
+                );
+                false
+            }
+            (_, "dir") => {
+                error!(
+                    "Uploading the output directory '{}' is not supported, use a file output type",
+                    output
+                );
+                false
+            }
+            _ => true,
+        };
+        // Every volume is uploaded followed by the volumes index
+        let files = match max_volume_size {
+            Some(_) => {
+                let index_path = VolumeSink::index_path(Path::new(output));
+                match VolumeIndex::from_path(&index_path) {
+                    Ok(index) => {
+                        let mut files: Vec<String> =
+                            index.volumes.into_iter().map(|v| v.path).collect();
+                        files.push(index_path.to_string_lossy().to_string());
+                        files
+                    }
                     Err(e) => {
-                        error!("Error paring upload configuration, ERROR: {:?}", e)
+                        error!(
+                            "Unable to read the volumes index '{}', ERROR: '{}'",
+                            index_path.to_string_lossy(),
+                            e
+                        );
+                        vec![]
+                    }
+                }
+            }
+            None => vec![output.to_string()],
+        };
+        for value in config.into_iter().filter(|_| uploadable) {
+            match UploadArtifacts::new(value) {
+                Ok(upload_artifact) => {
+                    for file in files.iter() {
+                        match upload_artifact.upload(file) {
+                            Ok(_) => {
+                                info!("Successfully uploaded the artifact package '{}'", file)
+                            }
+                            Err(e) => {
+                                error!(
+                                    "Unable to upload the artifact package '{}', ERROR: {:?}",
+                                    file, e
+                                );
+                            }
+                        }
                     }
                 }
+                Err(e) => {
+                    error!("Error paring upload configuration, ERROR: {:?}", e)
+                }
             }
         }
-        None => {}
     }
 
     for path in to_cleanup {
@@ -652,5 +1351,35 @@ fn main() {
         }
     }
 
+    if !collected {
+        exit(1);
+    }
     info!("Done!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4 * 1024));
+        assert_eq!(parse_size("10MB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size(" 2GiB "), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1t"), Ok(1024 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("100B"), Ok(100));
+    }
+
+    #[test]
+    fn parse_size_errors() {
+        assert_eq!(parse_size("5P"), Err(String::from("unknown size unit 'P'")));
+        assert!(parse_size("").unwrap_err().starts_with("invalid size ''"));
+        assert!(parse_size("M").unwrap_err().starts_with("invalid size 'M'"));
+        assert!(parse_size("1.5G").is_err());
+        assert_eq!(
+            parse_size("99999999999T"),
+            Err(String::from("the size '99999999999T' is too large"))
+        );
+    }
+}